@@ -0,0 +1,68 @@
+use crate::ray::Ray;
+use crate::vec3::Point3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub minimum: Point3,
+    pub maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Aabb {
+        Aabb { minimum, maximum }
+    }
+
+    pub fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t0 = (self.minimum[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.maximum[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // Written so that a NaN slab (ray origin on the slab plane) keeps the old bounds
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
+        Aabb {
+            minimum: Point3::new(
+                box0.minimum.x.min(box1.minimum.x),
+                box0.minimum.y.min(box1.minimum.y),
+                box0.minimum.z.min(box1.minimum.z),
+            ),
+            maximum: Point3::new(
+                box0.maximum.x.max(box1.maximum.x),
+                box0.maximum.y.max(box1.maximum.y),
+                box0.maximum.z.max(box1.maximum.z),
+            ),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.maximum - self.minimum;
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use rand::Rng;
+
+// Rectangles are too thin for the slab test along their normal, so their boxes get some depth
+const PADDING: f64 = 1.0e-4;
+
+/// Defines a rectangle spanning `[a0, a1] x [b0, b1]` in the plane where the `k` axis is
+/// constant. The outward normal points along +k and `u`, `v` follow the `a` and `b` axes.
+macro_rules! aarect {
+    ($(#[$doc: meta])* $name: ident, ($a: ident, $a0: ident, $a1: ident), ($b: ident, $b0: ident, $b1: ident), $k: ident) => {
+        $(#[$doc])*
+        pub struct $name {
+            $a0: f64,
+            $a1: f64,
+            $b0: f64,
+            $b1: f64,
+            k: f64,
+            material: Box<dyn Material>,
+        }
+
+        impl $name {
+            pub fn new(
+                ($a0, $a1): (f64, f64),
+                ($b0, $b1): (f64, f64),
+                k: f64,
+                material: impl Material + 'static,
+            ) -> Self {
+                $name {
+                    $a0: $a0.min($a1),
+                    $a1: $a0.max($a1),
+                    $b0: $b0.min($b1),
+                    $b1: $b0.max($b1),
+                    k,
+                    material: Box::new(material),
+                }
+            }
+
+            fn area(&self) -> f64 {
+                (self.$a1 - self.$a0) * (self.$b1 - self.$b0)
+            }
+        }
+
+        impl Hittable for $name {
+            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+                // Ray parallel to the rectangle
+                if ray.direction.$k.abs() < 1.0e-12 {
+                    return None;
+                }
+
+                let t = (self.k - ray.origin.$k) / ray.direction.$k;
+                if t < t_min || t > t_max {
+                    return None;
+                }
+
+                let p = ray.at(t);
+                if p.$a < self.$a0 || p.$a > self.$a1 || p.$b < self.$b0 || p.$b > self.$b1 {
+                    return None;
+                }
+
+                let mut rec = HitRecord::new(p, t, Some(self.material.as_ref()));
+                rec.u = (p.$a - self.$a0) / (self.$a1 - self.$a0);
+                rec.v = (p.$b - self.$b0) / (self.$b1 - self.$b0);
+                let mut outward_normal = Vec3::default();
+                outward_normal.$k = 1.0;
+                rec.set_face_normal(ray, outward_normal);
+
+                Some(rec)
+            }
+
+            fn bounding_box(&self) -> Option<Aabb> {
+                let mut minimum = Point3::default();
+                let mut maximum = Point3::default();
+                minimum.$a = self.$a0;
+                maximum.$a = self.$a1;
+                minimum.$b = self.$b0;
+                maximum.$b = self.$b1;
+                minimum.$k = self.k - PADDING;
+                maximum.$k = self.k + PADDING;
+                Some(Aabb::new(minimum, maximum))
+            }
+
+            fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+                let rec = match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::INFINITY) {
+                    Some(rec) => rec,
+                    None => return 0.0,
+                };
+
+                // Convert the uniform area density to solid angle
+                let distance_squared = rec.t * rec.t * direction.length_squared();
+                let cosine = (direction.$k / direction.length()).abs();
+                if cosine < 1.0e-8 {
+                    return 0.0;
+                }
+
+                distance_squared / (cosine * self.area())
+            }
+
+            fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+                let mut point = Point3::default();
+                point.$a = sampler.gen_range(self.$a0..=self.$a1);
+                point.$b = sampler.gen_range(self.$b0..=self.$b1);
+                point.$k = self.k;
+                point - origin
+            }
+        }
+    };
+}
+
+aarect!(
+    /// Rectangle parallel to the xy plane at `z = k`
+    XyRect,
+    (x, x0, x1),
+    (y, y0, y1),
+    z
+);
+aarect!(
+    /// Rectangle parallel to the xz plane at `y = k`
+    XzRect,
+    (x, x0, x1),
+    (z, z0, z1),
+    y
+);
+aarect!(
+    /// Rectangle parallel to the yz plane at `x = k`
+    YzRect,
+    (y, y0, y1),
+    (z, z0, z1),
+    x
+);
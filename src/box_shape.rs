@@ -0,0 +1,59 @@
+use crate::aabb::Aabb;
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::hittable::{Flipped, HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+
+/// Axis-aligned box made of six rectangles with outward facing normals
+pub struct BoxShape {
+    minimum: Point3,
+    maximum: Point3,
+    sides: HittableList,
+}
+
+impl BoxShape {
+    pub fn new(p0: Point3, p1: Point3, material: impl Material + Clone + 'static) -> Self {
+        let minimum = Point3::new(p0.x.min(p1.x), p0.y.min(p1.y), p0.z.min(p1.z));
+        let maximum = Point3::new(p0.x.max(p1.x), p0.y.max(p1.y), p0.z.max(p1.z));
+        let (x, y, z) = (
+            (minimum.x, maximum.x),
+            (minimum.y, maximum.y),
+            (minimum.z, maximum.z),
+        );
+
+        let mut sides = HittableList::new();
+        sides.add(XyRect::new(x, y, maximum.z, material.clone()));
+        sides.add(Flipped(XyRect::new(x, y, minimum.z, material.clone())));
+        sides.add(XzRect::new(x, z, maximum.y, material.clone()));
+        sides.add(Flipped(XzRect::new(x, z, minimum.y, material.clone())));
+        sides.add(YzRect::new(y, z, maximum.x, material.clone()));
+        sides.add(Flipped(YzRect::new(y, z, minimum.x, material)));
+
+        BoxShape {
+            minimum,
+            maximum,
+            sides,
+        }
+    }
+}
+
+impl Hittable for BoxShape {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.minimum, self.maximum))
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        self.sides.random(origin, sampler)
+    }
+}
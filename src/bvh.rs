@@ -0,0 +1,366 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+
+const MAX_LEAF_SIZE: usize = 4;
+const SAH_BUCKETS: usize = 12;
+// Relative cost of one ray/box test compared to one ray/primitive test
+const TRAVERSAL_COST: f64 = 0.125;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Surface area heuristic, evaluated over a fixed number of centroid buckets
+    Sah,
+    /// Split at the median centroid along the longest axis
+    Median,
+}
+
+/// A primitive paired with its cached bounding box while the tree is built
+type Primitive<T> = (T, Aabb);
+
+/// Bounding volume hierarchy over primitives of type `T`. Scenes use the default boxed
+/// trait objects, while e.g. meshes store their faces directly to avoid one allocation each.
+///
+/// Primitives without a bounding box, such as planes, cannot be culled. They are kept in a
+/// leaf next to the tree that every ray is tested against.
+pub struct BvhNode<T = Box<dyn Hittable + Sync + Send>> {
+    /// `None` if the node holds unbounded primitives, or none at all
+    bbox: Option<Aabb>,
+    children: Children<T>,
+}
+
+enum Children<T> {
+    Leaf(Vec<T>),
+    Split {
+        left: Box<BvhNode<T>>,
+        right: Box<BvhNode<T>>,
+        axis: usize,
+    },
+}
+
+impl<T: Hittable> BvhNode<T> {
+    pub fn from_primitives(objects: Vec<T>, split_method: SplitMethod) -> BvhNode<T> {
+        let mut primitives: Vec<Primitive<T>> = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => primitives.push((object, bbox)),
+                None => unbounded.push(object),
+            }
+        }
+
+        if primitives.is_empty() {
+            return BvhNode {
+                bbox: None,
+                children: Children::Leaf(unbounded),
+            };
+        }
+
+        let tree = Self::build(primitives, split_method);
+        if unbounded.is_empty() {
+            return tree;
+        }
+
+        BvhNode {
+            bbox: None,
+            children: Children::Split {
+                left: Box::new(tree),
+                right: Box::new(BvhNode {
+                    bbox: None,
+                    children: Children::Leaf(unbounded),
+                }),
+                axis: 0,
+            },
+        }
+    }
+
+    fn build(mut primitives: Vec<Primitive<T>>, split_method: SplitMethod) -> BvhNode<T> {
+        let bbox = primitives
+            .iter()
+            .map(|(_, b)| *b)
+            .reduce(Aabb::surrounding_box)
+            .unwrap();
+
+        if primitives.len() <= MAX_LEAF_SIZE {
+            return Self::leaf(primitives, bbox);
+        }
+
+        let centroid_box = primitives
+            .iter()
+            .map(|(_, b)| Aabb::new(b.centroid(), b.centroid()))
+            .reduce(Aabb::surrounding_box)
+            .unwrap();
+        let axis = centroid_box.longest_axis();
+        let extent = centroid_box.maximum[axis] - centroid_box.minimum[axis];
+
+        // All centroids coincide, there is nothing sensible to split on
+        if extent <= 0.0 {
+            return Self::leaf(primitives, bbox);
+        }
+
+        let mid = match split_method {
+            SplitMethod::Median => Self::partition_median(&mut primitives, axis),
+            SplitMethod::Sah => {
+                match Self::partition_sah(&mut primitives, axis, &centroid_box, &bbox) {
+                    Some(mid) => mid,
+                    None => return Self::leaf(primitives, bbox),
+                }
+            }
+        };
+
+        let right = primitives.split_off(mid);
+        let left = primitives;
+
+        BvhNode {
+            bbox: Some(bbox),
+            children: Children::Split {
+                left: Box::new(Self::build(left, split_method)),
+                right: Box::new(Self::build(right, split_method)),
+                axis,
+            },
+        }
+    }
+
+    fn leaf(primitives: Vec<Primitive<T>>, bbox: Aabb) -> BvhNode<T> {
+        BvhNode {
+            bbox: Some(bbox),
+            children: Children::Leaf(primitives.into_iter().map(|(object, _)| object).collect()),
+        }
+    }
+
+    fn partition_median(primitives: &mut [Primitive<T>], axis: usize) -> usize {
+        let mid = primitives.len() / 2;
+        primitives.select_nth_unstable_by(mid, |(_, a), (_, b)| {
+            a.centroid()[axis].total_cmp(&b.centroid()[axis])
+        });
+        mid
+    }
+
+    /// Returns the split index, or `None` if keeping the primitives in a single leaf is cheaper
+    fn partition_sah(
+        primitives: &mut [Primitive<T>],
+        axis: usize,
+        centroid_box: &Aabb,
+        bbox: &Aabb,
+    ) -> Option<usize> {
+        let min = centroid_box.minimum[axis];
+        let extent = centroid_box.maximum[axis] - min;
+        let bucket_of = |b: &Aabb| {
+            let offset = (b.centroid()[axis] - min) / extent;
+            ((offset * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bounds: [Option<Aabb>; SAH_BUCKETS] = [None; SAH_BUCKETS];
+        for (_, b) in primitives.iter() {
+            let i = bucket_of(b);
+            counts[i] += 1;
+            bounds[i] = Some(bounds[i].map_or(*b, |acc| Aabb::surrounding_box(acc, *b)));
+        }
+
+        // Cost of splitting after each bucket, relative to the parent's surface area
+        let mut best: Option<(usize, f64)> = None;
+        for split in 0..SAH_BUCKETS - 1 {
+            let (left_count, left_area) = Self::bucket_stats(&counts[..=split], &bounds[..=split]);
+            let (right_count, right_area) =
+                Self::bucket_stats(&counts[split + 1..], &bounds[split + 1..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + (left_count as f64 * left_area + right_count as f64 * right_area)
+                    / bbox.surface_area();
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((split, cost));
+            }
+        }
+
+        let (split, cost) = best?;
+        if cost >= primitives.len() as f64 && primitives.len() <= MAX_LEAF_SIZE * 4 {
+            return None;
+        }
+
+        // In-place partition by bucket
+        let mut mid = 0;
+        for i in 0..primitives.len() {
+            if bucket_of(&primitives[i].1) <= split {
+                primitives.swap(i, mid);
+                mid += 1;
+            }
+        }
+        Some(mid)
+    }
+
+    fn bucket_stats(counts: &[usize], bounds: &[Option<Aabb>]) -> (usize, f64) {
+        let count = counts.iter().sum();
+        let area = bounds
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(Aabb::surrounding_box)
+            .map_or(0.0, |b| b.surface_area());
+        (count, area)
+    }
+}
+
+impl<T: Hittable> Hittable for BvhNode<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if let Some(bbox) = &self.bbox {
+            if !bbox.hit(ray, t_min, t_max) {
+                return None;
+            }
+        }
+
+        match &self.children {
+            Children::Leaf(objects) => {
+                let mut temp_rec: Option<HitRecord> = None;
+                let mut closest_so_far = t_max;
+
+                for object in objects {
+                    if let Some(rec) = object.hit(ray, t_min, closest_so_far) {
+                        closest_so_far = rec.t;
+                        temp_rec.replace(rec);
+                    }
+                }
+
+                temp_rec
+            }
+            Children::Split { left, right, axis } => {
+                // Visit the child closer to the ray origin first so the far one can be culled
+                let (first, second) = if ray.direction[*axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                let first_hit = first.hit(ray, t_min, t_max);
+                let closest_so_far = first_hit.as_ref().map_or(t_max, |rec| rec.t);
+                second.hit(ray, t_min, closest_so_far).or(first_hit)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    fn random_spheres(seed: u64, count: usize) -> Vec<Sphere> {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let center = Point3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                let radius = rng.gen_range(0.05..1.5);
+                Sphere::new(center, radius, Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+            })
+            .collect()
+    }
+
+    fn plane() -> Plane {
+        Plane::new(
+            Point3::new(0.0, -4.0, 0.0),
+            Vec3::new(0.1, 1.0, -0.2),
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        )
+    }
+
+    /// Checks that `bvh` finds the same closest hits as a linear scan over `list`
+    fn assert_same_hits(bvh: &impl Hittable, list: &HittableList, seed: u64) {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Point3::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let direction = Vec3::random_unit_vector(&mut rng);
+            let ray = Ray::new(origin, direction, 0.0);
+
+            let expected = list.hit(&ray, 0.001, f64::INFINITY);
+            let actual = bvh.hit(&ray, 0.001, f64::INFINITY);
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    assert_eq!(expected.t, actual.t);
+                    assert_eq!(expected.normal, actual.normal);
+                    hits += 1;
+                }
+                (expected, actual) => panic!(
+                    "the list hit at {:?} but the BVH at {:?}",
+                    expected.map(|rec| rec.t),
+                    actual.map(|rec| rec.t)
+                ),
+            }
+        }
+        assert!(hits > 100, "only {} rays hit anything", hits);
+    }
+
+    fn check_against_list(split_method: SplitMethod, with_plane: bool) {
+        for seed in 0..4 {
+            let mut list = HittableList::new();
+            for sphere in random_spheres(seed, 200) {
+                list.add(sphere);
+            }
+            let mut objects: Vec<Box<dyn Hittable + Sync + Send>> = random_spheres(seed, 200)
+                .into_iter()
+                .map(|sphere| Box::new(sphere) as Box<dyn Hittable + Sync + Send>)
+                .collect();
+            if with_plane {
+                list.add(plane());
+                objects.push(Box::new(plane()));
+            }
+
+            let bvh = BvhNode::from_primitives(objects, split_method);
+            assert_same_hits(&bvh, &list, seed);
+        }
+    }
+
+    #[test]
+    fn sah_matches_list() {
+        check_against_list(SplitMethod::Sah, false);
+    }
+
+    #[test]
+    fn median_matches_list() {
+        check_against_list(SplitMethod::Median, false);
+    }
+
+    #[test]
+    fn unbounded_primitives_are_kept() {
+        check_against_list(SplitMethod::Sah, true);
+        check_against_list(SplitMethod::Median, true);
+    }
+
+    #[test]
+    fn empty_tree_hits_nothing() {
+        let bvh: BvhNode = BvhNode::from_primitives(Vec::new(), SplitMethod::Sah);
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(bvh.hit(&ray, 0.001, f64::INFINITY).is_none());
+        assert!(bvh.bounding_box().is_none());
+    }
+
+    #[test]
+    fn only_unbounded_primitives() {
+        let bvh = BvhNode::from_primitives(vec![plane()], SplitMethod::Median);
+        let ray = Ray::new(Point3::default(), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = bvh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p.y + 4.0).abs() < 1e-9);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
     ray::Ray,
+    sampler::Sampler,
     vec3::{Point3, Vec3},
 };
+use rand::Rng;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Camera {
@@ -11,8 +13,9 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -39,10 +42,11 @@ impl Camera {
             horizontal: focus_dist * viewport_width * u,
             vertical: focus_dist * viewport_height * v,
             lower_left_corner: Point3::default(),
-            w,
             u,
             v,
             lens_radius: aperture / 2.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         };
 
         camera.lower_left_corner =
@@ -51,13 +55,27 @@ impl Camera {
         camera
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd: Vec3 = self.lens_radius * Vec3::random_in_unit_disk();
+    /// Spreads the rays uniformly over the time interval the shutter is open
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut Sampler) -> Ray {
+        let rd: Vec3 = self.lens_radius * Vec3::random_in_unit_disk(sampler);
         let offset: Vec3 = self.u * rd.x + self.v * rd.y;
+        // A closed shutter leaves the sample stream of still images unchanged
+        let time = if self.shutter_close > self.shutter_open {
+            sampler.gen_range(self.shutter_open..self.shutter_close)
+        } else {
+            self.shutter_open
+        };
 
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time,
         )
     }
 }
@@ -0,0 +1,307 @@
+//! Checkpoints of a render in progress, so a render that stopped can be resumed.
+//!
+//! A checkpoint holds the sample sums of every pixel together with the settings and the
+//! fingerprint of the scene that decide what the samples are. The random numbers of a
+//! sample only depend on the seed, the pixel and the index of the sample, see `Sampler`,
+//! so the seed and the sample count of every pixel are all the random number state there
+//! is, and a resumed render ends up with the same image as one that never stopped.
+//!
+//! The file is binary and little endian: a magic number and version, the settings, the
+//! scene fingerprint, then the sums of every pixel row by row from the top.
+use crate::renderer::{Accumulation, PixelSums};
+use crate::scene::RenderSettings;
+use crate::vec3::Color;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RTCP";
+const VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// Not a checkpoint, or one of a different version
+    Format(&'static str),
+    /// The checkpoint belongs to a render with different settings
+    Mismatch {
+        setting: &'static str,
+        checkpoint: String,
+        render: String,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::Format(message) => write!(f, "invalid checkpoint, {}", message),
+            CheckpointError::Mismatch {
+                setting,
+                checkpoint,
+                render,
+            } => write!(
+                f,
+                "the checkpoint was made with {} {}, not {}",
+                setting, checkpoint, render
+            ),
+        }
+    }
+}
+
+impl Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheckpointError::Io(err) => Some(err),
+            CheckpointError::Format(_) | CheckpointError::Mismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+/// Render state read back from a checkpoint file
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub image_width: i32,
+    pub image_height: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples_per_pixel: i32,
+    pub roulette_depth: i32,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    /// Fingerprint of the scene, see `distributed::scene_fingerprint`
+    pub scene: u64,
+    pub accumulation: Accumulation,
+}
+
+impl Checkpoint {
+    /// Checks that resuming with `settings` in the scene with the fingerprint `scene`
+    /// renders the same samples the checkpoint was made of
+    pub fn check(&self, settings: &RenderSettings, scene: u64) -> Result<(), CheckpointError> {
+        fn compare<T: PartialEq + fmt::Debug>(
+            setting: &'static str,
+            checkpoint: T,
+            render: T,
+        ) -> Result<(), CheckpointError> {
+            if checkpoint == render {
+                return Ok(());
+            }
+            Err(CheckpointError::Mismatch {
+                setting,
+                checkpoint: format!("{:?}", checkpoint),
+                render: format!("{:?}", render),
+            })
+        }
+
+        compare("scene", Fingerprint(self.scene), Fingerprint(scene))?;
+        compare(
+            "image size",
+            (self.image_width, self.image_height),
+            (settings.image_width, settings.image_height),
+        )?;
+        compare(
+            "samples per pixel",
+            self.samples_per_pixel,
+            settings.samples_per_pixel,
+        )?;
+        compare("maximum depth", self.max_depth, settings.max_depth)?;
+        compare("seed", self.seed, settings.seed)?;
+        compare(
+            "adaptive threshold",
+            self.adaptive_threshold,
+            settings.adaptive_threshold,
+        )?;
+        compare(
+            "roulette depth",
+            self.roulette_depth,
+            settings.roulette_depth,
+        )?;
+        compare("diffuse depth", self.diffuse_depth, settings.diffuse_depth)?;
+        compare(
+            "specular depth",
+            self.specular_depth,
+            settings.specular_depth,
+        )?;
+        compare(
+            "transmission depth",
+            self.transmission_depth,
+            settings.transmission_depth,
+        )?;
+        if self.adaptive_threshold.is_some() {
+            compare(
+                "minimum samples per pixel",
+                self.min_samples_per_pixel,
+                settings.min_samples_per_pixel,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Fingerprint in the messages of mismatches, as the hexadecimal number it is
+#[derive(PartialEq)]
+struct Fingerprint(u64);
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Writes a checkpoint of `accumulation` rendered with `settings` in the scene with the
+/// fingerprint `scene` to `path`, through a temporary file next to it so that a crash
+/// while saving keeps the previous checkpoint
+pub fn save(
+    path: &Path,
+    settings: &RenderSettings,
+    scene: u64,
+    accumulation: &Accumulation,
+) -> Result<(), CheckpointError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".partial");
+
+    let mut stream = BufWriter::new(File::create(&temporary)?);
+    stream.write_all(MAGIC)?;
+    stream.write_all(&VERSION.to_le_bytes())?;
+    for value in &[
+        settings.image_width,
+        settings.image_height,
+        settings.samples_per_pixel,
+        settings.max_depth,
+        settings.min_samples_per_pixel,
+        settings.roulette_depth,
+        // Limits are non-negative, -1 means none
+        settings.diffuse_depth.unwrap_or(-1),
+        settings.specular_depth.unwrap_or(-1),
+        settings.transmission_depth.unwrap_or(-1),
+    ] {
+        stream.write_all(&value.to_le_bytes())?;
+    }
+    stream.write_all(&settings.seed.to_le_bytes())?;
+    // Thresholds are positive, 0 means no adaptive sampling
+    stream.write_all(&settings.adaptive_threshold.unwrap_or(0.0).to_le_bytes())?;
+    stream.write_all(&scene.to_le_bytes())?;
+
+    for sums in &accumulation.pixels {
+        for value in &[
+            sums.color.x,
+            sums.color.y,
+            sums.color.z,
+            sums.luminance_squares,
+        ] {
+            stream.write_all(&value.to_le_bytes())?;
+        }
+        stream.write_all(&sums.samples.to_le_bytes())?;
+    }
+
+    stream.flush()?;
+    drop(stream);
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Reads the checkpoint at `path`
+pub fn load(path: &Path) -> Result<Checkpoint, CheckpointError> {
+    let mut stream = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 4];
+    stream.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(CheckpointError::Format("unknown file type"));
+    }
+    if read_u32(&mut stream)? != VERSION {
+        return Err(CheckpointError::Format("unsupported version"));
+    }
+
+    let image_width = read_i32(&mut stream)?;
+    let image_height = read_i32(&mut stream)?;
+    let samples_per_pixel = read_i32(&mut stream)?;
+    let max_depth = read_i32(&mut stream)?;
+    let min_samples_per_pixel = read_i32(&mut stream)?;
+    let roulette_depth = read_i32(&mut stream)?;
+    let diffuse_depth = read_depth_limit(&mut stream)?;
+    let specular_depth = read_depth_limit(&mut stream)?;
+    let transmission_depth = read_depth_limit(&mut stream)?;
+    let seed = read_u64(&mut stream)?;
+    let adaptive_threshold = Some(read_f64(&mut stream)?).filter(|threshold| *threshold > 0.0);
+    let scene = read_u64(&mut stream)?;
+    if image_width <= 0 || image_height <= 0 {
+        return Err(CheckpointError::Format("empty image"));
+    }
+    if image_width.checked_mul(image_height).is_none() {
+        return Err(CheckpointError::Format("image too large"));
+    }
+
+    let mut accumulation = Accumulation::new(image_width as usize, image_height as usize);
+    for sums in &mut accumulation.pixels {
+        let color = Color::new(
+            read_f64(&mut stream)?,
+            read_f64(&mut stream)?,
+            read_f64(&mut stream)?,
+        );
+        *sums = PixelSums {
+            color,
+            luminance_squares: read_f64(&mut stream)?,
+            samples: read_i32(&mut stream)?,
+        };
+        if sums.samples < 0 {
+            return Err(CheckpointError::Format("negative sample count"));
+        }
+    }
+    if stream.read(&mut [0])? != 0 {
+        return Err(CheckpointError::Format("trailing data"));
+    }
+
+    Ok(Checkpoint {
+        image_width,
+        image_height,
+        samples_per_pixel,
+        max_depth,
+        seed,
+        adaptive_threshold,
+        min_samples_per_pixel,
+        roulette_depth,
+        diffuse_depth,
+        specular_depth,
+        transmission_depth,
+        scene,
+        accumulation,
+    })
+}
+
+fn read_bytes<const N: usize>(stream: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(stream: &mut impl Read) -> io::Result<u32> {
+    read_bytes(stream).map(u32::from_le_bytes)
+}
+
+fn read_i32(stream: &mut impl Read) -> io::Result<i32> {
+    read_bytes(stream).map(i32::from_le_bytes)
+}
+
+fn read_depth_limit(stream: &mut impl Read) -> io::Result<Option<i32>> {
+    read_i32(stream).map(|depth| Some(depth).filter(|depth| *depth >= 0))
+}
+
+fn read_u64(stream: &mut impl Read) -> io::Result<u64> {
+    read_bytes(stream).map(u64::from_le_bytes)
+}
+
+fn read_f64(stream: &mut impl Read) -> io::Result<f64> {
+    read_bytes(stream).map(f64::from_le_bytes)
+}
@@ -0,0 +1,203 @@
+//! Chi-square test of a sampling routine against the density it claims to sample, for the
+//! tests of the materials and microfacet distributions. Sampled directions are binned by
+//! their spherical coordinates and the counts compared with the density integrated over
+//! each bin.
+use crate::vec3::Vec3;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use std::f64::consts::PI;
+
+const THETA_BINS: usize = 10;
+const PHI_BINS: usize = 20;
+/// Bins expecting fewer samples than this are pooled, the statistic needs a few in each
+const MIN_EXPECTED: f64 = 5.0;
+/// Standard scores above this fail the test, the odds of a correct sampler doing so are
+/// about one in a million
+const MAX_SCORE: f64 = 4.75;
+
+/// Draws `count` directions with `sample` and checks they are distributed by `pdf`.
+/// `sample` returns `None` for samples the density does not cover, e.g. specular lobes or
+/// absorbed paths, as many as `pdf` leaves of the probability.
+pub fn assert_samples_match(
+    name: &str,
+    count: usize,
+    mut sample: impl FnMut(&mut Pcg32) -> Option<Vec3>,
+    pdf: impl Fn(Vec3) -> f64,
+) {
+    let mut rng = Pcg32::seed_from_u64(7);
+    let mut observed = vec![0.0; THETA_BINS * PHI_BINS + 1];
+    for _ in 0..count {
+        let bin = match sample(&mut rng) {
+            Some(direction) => bin(direction),
+            None => THETA_BINS * PHI_BINS,
+        };
+        observed[bin] += 1.0;
+    }
+
+    let bin_width = (PI / THETA_BINS as f64, 2.0 * PI / PHI_BINS as f64);
+    let density = |theta: f64, phi: f64| {
+        let direction = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        );
+        pdf(direction) * theta.sin()
+    };
+    // A hundredth of a sample is plenty next to the noise of the counts
+    let tolerance = 0.01 / count as f64;
+    let mut expected: Vec<f64> = (0..THETA_BINS * PHI_BINS)
+        .map(|bin| {
+            let theta = (bin / PHI_BINS) as f64 * bin_width.0;
+            let phi = (bin % PHI_BINS) as f64 * bin_width.1;
+            let rect = [theta, theta + bin_width.0, phi, phi + bin_width.1];
+            count as f64 * integrate(&density, rect, tolerance)
+        })
+        .collect();
+    let covered: f64 = expected.iter().sum();
+    expected.push((count as f64 - covered).max(0.0));
+
+    let (statistic, cells) = chi_square(&observed, &expected);
+    // E.g. a mirror, whose samples the density does not cover at all
+    if cells < 2 {
+        return;
+    }
+    let degrees = (cells - 1) as f64;
+    // Wilson-Hilferty transform of the statistic to a standard normal score
+    let variance = 2.0 / (9.0 * degrees);
+    let score = ((statistic / degrees).cbrt() - (1.0 - variance)) / variance.sqrt();
+    assert!(
+        score < MAX_SCORE,
+        "{}: chi-square {:.1} with {} degrees of freedom, standard score {:.2}",
+        name,
+        statistic,
+        degrees,
+        score
+    );
+}
+
+fn bin(direction: Vec3) -> usize {
+    let direction = direction.normalize();
+    let theta = direction.z.clamp(-1.0, 1.0).acos();
+    let phi = direction.y.atan2(direction.x).rem_euclid(2.0 * PI);
+    let i = ((theta / PI * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+    let j = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+    i * PHI_BINS + j
+}
+
+/// Integral of `f` over the rectangle `[x0, x1, y0, y1]` by adaptive Simpson quadrature,
+/// which refines around the sharp peaks of glossy lobes until the error of every piece is
+/// below `tolerance` or a small fraction of its value
+fn integrate(f: &impl Fn(f64, f64) -> f64, rect: [f64; 4], tolerance: f64) -> f64 {
+    let [x0, x1, y0, y1] = rect;
+    let mut corners = [[0.0; 3]; 3];
+    for (i, row) in corners.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = f(
+                x0 + 0.5 * i as f64 * (x1 - x0),
+                y0 + 0.5 * j as f64 * (y1 - y0),
+            );
+        }
+    }
+    refine(f, rect, corners, tolerance, 0)
+}
+
+/// Refines the Simpson estimate of the rectangle from the values `f` takes on its 3 by 3
+/// grid, by comparing it with the estimates of its quarters
+fn refine(
+    f: &impl Fn(f64, f64) -> f64,
+    rect: [f64; 4],
+    grid: [[f64; 3]; 3],
+    tolerance: f64,
+    depth: u32,
+) -> f64 {
+    let [x0, x1, y0, y1] = rect;
+    // The grid of the quarters, every other value of which is known
+    let mut values = [[0.0; 5]; 5];
+    for (i, row) in values.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = if i % 2 == 0 && j % 2 == 0 {
+                grid[i / 2][j / 2]
+            } else {
+                f(
+                    x0 + 0.25 * i as f64 * (x1 - x0),
+                    y0 + 0.25 * j as f64 * (y1 - y0),
+                )
+            };
+        }
+    }
+
+    let quarter = |i: usize, j: usize| {
+        let mut grid = [[0.0; 3]; 3];
+        for (k, row) in grid.iter_mut().enumerate() {
+            for (l, value) in row.iter_mut().enumerate() {
+                *value = values[2 * i + k][2 * j + l];
+            }
+        }
+        let (width, height) = (0.5 * (x1 - x0), 0.5 * (y1 - y0));
+        let x = x0 + i as f64 * width;
+        let y = y0 + j as f64 * height;
+        ([x, x + width, y, y + height], grid)
+    };
+    let quarters = [quarter(0, 0), quarter(0, 1), quarter(1, 0), quarter(1, 1)];
+
+    let coarse = simpson(rect, &grid);
+    let fine: f64 = quarters
+        .iter()
+        .map(|(rect, grid)| simpson(*rect, grid))
+        .sum();
+    // Always split once, a coarse grid may miss a narrow lobe altogether
+    let converged = (fine - coarse).abs() <= tolerance + 1.0e-3 * fine.abs();
+    if depth >= 8 || (depth >= 1 && converged) {
+        return fine + (fine - coarse) / 15.0;
+    }
+    quarters
+        .iter()
+        .map(|&(rect, grid)| refine(f, rect, grid, tolerance, depth + 1))
+        .sum()
+}
+
+fn simpson([x0, x1, y0, y1]: [f64; 4], grid: &[[f64; 3]; 3]) -> f64 {
+    let weights = [1.0, 4.0, 1.0];
+    let mut sum = 0.0;
+    for (row, wx) in grid.iter().zip(&weights) {
+        for (value, wy) in row.iter().zip(&weights) {
+            sum += wx * wy * value;
+        }
+    }
+    sum * (x1 - x0) * (y1 - y0) / 36.0
+}
+
+/// Pearson's statistic over the cells, those expecting few samples pooled into one, and the
+/// number of cells it was computed over
+fn chi_square(observed: &[f64], expected: &[f64]) -> (f64, usize) {
+    let mut cells: Vec<(f64, f64)> = observed
+        .iter()
+        .copied()
+        .zip(expected.iter().copied())
+        .collect();
+    cells.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let mut pooled = (0.0, 0.0);
+    let mut kept = Vec::new();
+    for (observed, expected) in cells {
+        if expected < MIN_EXPECTED {
+            pooled.0 += observed;
+            pooled.1 += expected;
+        } else {
+            kept.push((observed, expected));
+        }
+    }
+    if pooled.1 >= MIN_EXPECTED {
+        kept.push(pooled);
+    } else if let Some(smallest) = kept.first_mut() {
+        // Too few to stand on their own, but samples where none belong must still count
+        smallest.0 += pooled.0;
+        smallest.1 += pooled.1;
+    }
+
+    let statistic = kept
+        .iter()
+        .map(|(observed, expected)| (observed - expected).powi(2) / expected)
+        .sum();
+    (statistic, kept.len())
+}
@@ -0,0 +1,392 @@
+use crate::output::BitDepth;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: raytracing [OPTIONS]
+
+Options:
+    --scene <PATH>       Scene description file, renders the random sphere scene if omitted
+    --width <PIXELS>     Image width, keeps the scene's aspect ratio unless --height is given
+    --height <PIXELS>    Image height, keeps the scene's aspect ratio unless --width is given
+    --spp <N>            Samples per pixel
+    --max-depth <N>      Maximum number of bounces per path
+    --roulette-depth <N> Bounces before Russian roulette may end a path, defaults to 3
+    --diffuse-depth <N>  Maximum number of diffuse bounces per path
+    --specular-depth <N> Maximum number of specular and glossy reflections per path
+    --transmission-depth <N>
+                         Maximum number of transmissions through surfaces per path
+    --output <PATH>      Output image, the format follows the extension (.ppm, .png, .hdr, .exr),
+                         '-' or omitted writes a plain text PPM to stdout
+    --bit-depth <8|16>   Bits per channel of PNG output
+    --seed <N>           Seed of the random sphere scene and of all render samples,
+                         a random one is picked and printed if omitted
+    --threads <N>        Number of render threads, defaults to the number of CPUs
+    --tile-size <PIXELS> Side length of the square tiles the image is rendered in,
+                         defaults to 32
+    --adaptive <ERR>     Stop sampling a pixel once the standard error of its brightness falls
+                         below ERR times the brightness, --spp becomes the maximum
+    --min-spp <N>        Samples per pixel before adaptive sampling may stop, defaults to 16
+    --sample-map <PATH>  Also write an image of the samples each pixel got, white is --spp
+    --progressive <SEC>  Render in passes of 1, 2, 4, ... samples per pixel and rewrite the
+                         output file after each pass once SEC seconds have passed since the
+                         last write, so a stopped render still leaves an image
+    --checkpoint <PATH>  Save the samples rendered so far to PATH every --checkpoint-interval
+                         and on Ctrl-C, which stops a local render after the current tiles
+    --checkpoint-interval <SEC>
+                         Seconds between checkpoints, defaults to 60
+    --resume             Continue the render saved in --checkpoint, the scene and settings
+                         have to be the same and the seed defaults to the saved one
+    --serve <ADDR>       Render with the workers connecting to ADDR, e.g. 0.0.0.0:7878,
+                         instead of locally
+    --worker <ADDR>      Render tiles for the --serve coordinator at ADDR, with the same
+                         --scene, everything else comes from the coordinator
+    -h, --help           Print this help";
+
+const FLAGS: [&str; 22] = [
+    "--scene",
+    "--width",
+    "--height",
+    "--spp",
+    "--max-depth",
+    "--roulette-depth",
+    "--diffuse-depth",
+    "--specular-depth",
+    "--transmission-depth",
+    "--output",
+    "--bit-depth",
+    "--seed",
+    "--threads",
+    "--tile-size",
+    "--adaptive",
+    "--min-spp",
+    "--sample-map",
+    "--progressive",
+    "--checkpoint",
+    "--checkpoint-interval",
+    "--serve",
+    "--worker",
+];
+
+/// Flags without a value
+const SWITCHES: [&str; 1] = ["--resume"];
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub samples_per_pixel: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub roulette_depth: Option<i32>,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub output: Option<PathBuf>,
+    pub bit_depth: Option<BitDepth>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub tile_size: Option<usize>,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples_per_pixel: Option<i32>,
+    pub sample_map: Option<PathBuf>,
+    /// Seconds between image writes of a progressive render
+    pub progressive: Option<u64>,
+    pub checkpoint: Option<PathBuf>,
+    /// Seconds between checkpoints
+    pub checkpoint_interval: Option<u64>,
+    pub resume: bool,
+    /// Address to listen on for workers
+    pub serve: Option<String>,
+    /// Address of the coordinator to render for
+    pub worker: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    HelpRequested,
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+        expected: &'static str,
+    },
+    UnknownArgument(String),
+    DuplicateArgument(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::HelpRequested => write!(f, "{}", USAGE),
+            CliError::MissingValue(flag) => write!(f, "'{}' requires a value", flag),
+            CliError::InvalidValue {
+                flag,
+                value,
+                expected,
+            } => write!(f, "'{}' expects {}, found '{}'", flag, expected, value),
+            CliError::UnknownArgument(arg) => write!(f, "unknown argument '{}'", arg),
+            CliError::DuplicateArgument(flag) => write!(f, "'{}' given more than once", flag),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+fn set<T>(slot: &mut Option<T>, flag: &str, value: T) -> Result<(), CliError> {
+    if slot.replace(value).is_some() {
+        return Err(CliError::DuplicateArgument(flag.to_string()));
+    }
+    Ok(())
+}
+
+fn non_negative(flag: &str, value: &str) -> Result<i32, CliError> {
+    value
+        .parse::<i32>()
+        .ok()
+        .filter(|v| *v >= 0)
+        .ok_or_else(|| CliError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+            expected: "a non-negative integer",
+        })
+}
+
+fn positive<T: FromStr + PartialOrd + Default>(flag: &str, value: &str) -> Result<T, CliError> {
+    value
+        .parse::<T>()
+        .ok()
+        .filter(|v| *v > T::default())
+        .ok_or_else(|| CliError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+            expected: "a positive integer",
+        })
+}
+
+/// Parses the arguments following the program name. Values are given either as the
+/// next argument or joined with `=`, e.g. `--spp 100` or `--spp=100`. A value that starts
+/// with `--` has to be joined, otherwise it is taken for the next flag.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, CliError> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Err(CliError::HelpRequested);
+        }
+
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+
+        if SWITCHES.contains(&flag.as_str()) {
+            if let Some(value) = inline_value {
+                return Err(CliError::InvalidValue {
+                    flag,
+                    value,
+                    expected: "no value",
+                });
+            }
+            match flag.as_str() {
+                "--resume" if options.resume => return Err(CliError::DuplicateArgument(flag)),
+                "--resume" => options.resume = true,
+                _ => return Err(CliError::UnknownArgument(flag)),
+            }
+            continue;
+        }
+
+        if !FLAGS.contains(&flag.as_str()) {
+            return Err(CliError::UnknownArgument(flag));
+        }
+
+        let value = match inline_value.or_else(|| args.next_if(|next| !next.starts_with("--"))) {
+            Some(value) => value,
+            None => return Err(CliError::MissingValue(flag)),
+        };
+
+        match flag.as_str() {
+            "--scene" => set(&mut options.scene, &flag, PathBuf::from(value))?,
+            "--width" => set(&mut options.width, &flag, positive(&flag, &value)?)?,
+            "--height" => set(&mut options.height, &flag, positive(&flag, &value)?)?,
+            "--spp" => set(
+                &mut options.samples_per_pixel,
+                &flag,
+                positive(&flag, &value)?,
+            )?,
+            "--max-depth" => set(&mut options.max_depth, &flag, positive(&flag, &value)?)?,
+            "--roulette-depth" => set(
+                &mut options.roulette_depth,
+                &flag,
+                non_negative(&flag, &value)?,
+            )?,
+            "--diffuse-depth" => set(
+                &mut options.diffuse_depth,
+                &flag,
+                non_negative(&flag, &value)?,
+            )?,
+            "--specular-depth" => set(
+                &mut options.specular_depth,
+                &flag,
+                non_negative(&flag, &value)?,
+            )?,
+            "--transmission-depth" => set(
+                &mut options.transmission_depth,
+                &flag,
+                non_negative(&flag, &value)?,
+            )?,
+            "--output" => set(&mut options.output, &flag, PathBuf::from(value))?,
+            "--bit-depth" => {
+                let bit_depth = match value.as_str() {
+                    "8" => BitDepth::Eight,
+                    "16" => BitDepth::Sixteen,
+                    _ => {
+                        return Err(CliError::InvalidValue {
+                            flag,
+                            value,
+                            expected: "8 or 16",
+                        })
+                    }
+                };
+                set(&mut options.bit_depth, &flag, bit_depth)?
+            }
+            "--seed" => {
+                let seed = value.parse().map_err(|_| CliError::InvalidValue {
+                    flag: flag.clone(),
+                    value: value.clone(),
+                    expected: "a non-negative integer",
+                })?;
+                set(&mut options.seed, &flag, seed)?
+            }
+            "--threads" => set(&mut options.threads, &flag, positive(&flag, &value)?)?,
+            "--tile-size" => set(&mut options.tile_size, &flag, positive(&flag, &value)?)?,
+            "--adaptive" => {
+                let threshold = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| *v > 0.0 && v.is_finite())
+                    .ok_or_else(|| CliError::InvalidValue {
+                        flag: flag.clone(),
+                        value: value.clone(),
+                        expected: "a positive number",
+                    })?;
+                set(&mut options.adaptive_threshold, &flag, threshold)?
+            }
+            "--min-spp" => set(
+                &mut options.min_samples_per_pixel,
+                &flag,
+                positive(&flag, &value)?,
+            )?,
+            "--sample-map" => set(&mut options.sample_map, &flag, PathBuf::from(value))?,
+            "--progressive" => set(&mut options.progressive, &flag, positive(&flag, &value)?)?,
+            "--serve" => set(&mut options.serve, &flag, value)?,
+            "--worker" => set(&mut options.worker, &flag, value)?,
+            "--checkpoint" => set(&mut options.checkpoint, &flag, PathBuf::from(value))?,
+            "--checkpoint-interval" => set(
+                &mut options.checkpoint_interval,
+                &flag,
+                positive(&flag, &value)?,
+            )?,
+            _ => return Err(CliError::UnknownArgument(flag)),
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, CliError> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    fn error(args: &str) -> String {
+        parse(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn values_follow_or_join_their_flags() {
+        let options =
+            parse("--scene a.scene --width=64 --height 48 --spp=8 --output - --resume --seed 7")
+                .unwrap();
+        assert_eq!(options.scene, Some(PathBuf::from("a.scene")));
+        assert_eq!((options.width, options.height), (Some(64), Some(48)));
+        assert_eq!(options.samples_per_pixel, Some(8));
+        assert_eq!(options.output, Some(PathBuf::from("-")));
+        assert_eq!(options.seed, Some(7));
+        assert!(options.resume);
+
+        let options = parse("--output=--odd.png").unwrap();
+        assert_eq!(options.output, Some(PathBuf::from("--odd.png")));
+    }
+
+    #[test]
+    fn sizes_must_be_positive() {
+        for args in &[
+            "--width 0",
+            "--width -3",
+            "--height=0",
+            "--spp 0",
+            "--tile-size 0",
+            "--threads -1",
+        ] {
+            let flag = args.split([' ', '=']).next().unwrap();
+            let value = args[flag.len() + 1..].to_string();
+            assert_eq!(
+                error(args),
+                format!("'{}' expects a positive integer, found '{}'", flag, value)
+            );
+        }
+        assert_eq!(
+            error("--width 1.5"),
+            "'--width' expects a positive integer, found '1.5'"
+        );
+        assert_eq!(
+            error("--diffuse-depth -1"),
+            "'--diffuse-depth' expects a non-negative integer, found '-1'"
+        );
+        assert_eq!(parse("--diffuse-depth 0").unwrap().diffuse_depth, Some(0));
+    }
+
+    #[test]
+    fn flags_need_their_values() {
+        assert_eq!(error("--width"), "'--width' requires a value");
+        assert_eq!(error("--output --width 4"), "'--output' requires a value");
+        assert_eq!(error("--scene --resume"), "'--scene' requires a value");
+        assert_eq!(
+            error("--resume=yes"),
+            "'--resume' expects no value, found 'yes'"
+        );
+    }
+
+    #[test]
+    fn other_rejections() {
+        assert_eq!(error("--widht 4"), "unknown argument '--widht'");
+        assert_eq!(error("scene.txt"), "unknown argument 'scene.txt'");
+        assert_eq!(error("-o out.png"), "unknown argument '-o'");
+        assert_eq!(error("--spp 4 --spp=8"), "'--spp' given more than once");
+        assert_eq!(
+            error("--resume --resume"),
+            "'--resume' given more than once"
+        );
+        assert_eq!(
+            error("--bit-depth 12"),
+            "'--bit-depth' expects 8 or 16, found '12'"
+        );
+        assert_eq!(
+            error("--adaptive inf"),
+            "'--adaptive' expects a positive number, found 'inf'"
+        );
+        assert!(matches!(
+            parse("--width 4 --help"),
+            Err(CliError::HelpRequested)
+        ));
+    }
+}
@@ -2,27 +2,40 @@ use crate::vec3::Color;
 use std::io;
 use std::io::Write;
 
+/// Gamma-corrects (gamma 2) a linear color and clamps it to the displayable range
+pub fn to_display(pixel_color: Color) -> Color {
+    Color::new(
+        pixel_color.x.max(0.0).sqrt().min(1.0),
+        pixel_color.y.max(0.0).sqrt().min(1.0),
+        pixel_color.z.max(0.0).sqrt().min(1.0),
+    )
+}
+
+/// Perceived brightness of a linear color (Rec. 709 weights)
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Quantizes a display color channel in [0, 1] to `max + 1` levels
+pub fn quantize(channel: f64, max: u16) -> u16 {
+    ((channel * (max as f64 + 1.)) as i32).clamp(0, max as i32) as u16
+}
+
 pub fn write_color(
     stream: &mut impl Write,
     pixel_color: Color,
     samples_per_pixel: i32,
 ) -> Result<(), io::Error> {
-    let mut r = pixel_color.x;
-    let mut g = pixel_color.y;
-    let mut b = pixel_color.z;
-
     // Divide the color by the number of samples
     let scale = 1.0 / samples_per_pixel as f64;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
+    let display = to_display(scale * pixel_color);
 
     writeln!(
         stream,
         "{} {} {}",
-        ((r * (u8::MAX as f64 + 1.)) as i32).clamp(0, u8::MAX as i32),
-        ((g * (u8::MAX as f64 + 1.)) as i32).clamp(0, u8::MAX as i32),
-        ((b * (u8::MAX as f64 + 1.)) as i32).clamp(0, u8::MAX as i32)
+        quantize(display.x, u8::MAX as u16),
+        quantize(display.y, u8::MAX as u16),
+        quantize(display.z, u8::MAX as u16)
     )
     .map(|_| ())
 }
@@ -0,0 +1,107 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Homogeneous volume such as smoke or fog filling a closed `boundary`. A ray passing
+/// through it scatters at an exponentially distributed distance, which counts as a hit
+/// with the `phase_function` material, or leaves without touching it.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    phase_function: Box<dyn Material>,
+    neg_inv_density: f64,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: impl Hittable + Send + Sync + 'static,
+        density: f64,
+        phase_function: impl Material + 'static,
+    ) -> Self {
+        ConstantMedium {
+            boundary: Box::new(boundary),
+            phase_function: Box::new(phase_function),
+            neg_inv_density: -1.0 / density,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Entry and exit of the whole line, the ray may well start inside the volume
+        let entry = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?.t;
+        let exit = self.boundary.hit(ray, entry + 0.0001, f64::INFINITY)?.t;
+
+        let t_enter = entry.max(t_min).max(0.0);
+        let t_exit = exit.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_length = ray.direction.length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let u: f64 = Sampler::for_medium(ray.medium_seed, t_enter).gen();
+        let hit_distance = self.neg_inv_density * (1.0 - u).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        let mut rec = HitRecord::new(ray.at(t), t, Some(self.phase_function.as_ref()));
+        // A point inside a volume has no surface, any normal will do
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::box_shape::BoxShape;
+    use crate::material::Isotropic;
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn transmittance_is_exponential() {
+        let density = 0.7;
+        let medium = ConstantMedium::new(
+            BoxShape::new(
+                Point3::new(-1.0, -1.0, -1.0),
+                Point3::new(1.0, 1.0, 1.0),
+                Isotropic::new(Color::new(1.0, 1.0, 1.0)),
+            ),
+            density,
+            Isotropic::new(Color::new(1.0, 1.0, 1.0)),
+        );
+
+        // The same ray over and over, only the seeds of the camera samples differ
+        let mut sampler = Sampler::new(1, 0, 0);
+        let count = 100_000;
+        let passed = (0..count)
+            .filter(|_| {
+                let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0)
+                    .with_medium_seed(sampler.gen());
+                medium.hit(&ray, 0.001, f64::INFINITY).is_none()
+            })
+            .count();
+
+        let expected = (-density * 2.0).exp();
+        let error = (expected * (1.0 - expected) / count as f64).sqrt();
+        let fraction = passed as f64 / count as f64;
+        assert!(
+            (fraction - expected).abs() < 4.0 * error,
+            "{} of the rays passed, expected {}",
+            fraction,
+            expected
+        );
+    }
+}
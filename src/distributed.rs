@@ -0,0 +1,782 @@
+//! Rendering spread over several machines. A `Coordinator` listens for workers and hands
+//! out tiles, every `Worker` connects to it over TCP, renders the tiles it gets and sends
+//! their float sums back to be merged into the coordinator's accumulation.
+//!
+//! Workers load the scene themselves, the coordinator sends the render settings, the seed
+//! among them, and a fingerprint of the scene file and the files it refers to, so a worker
+//! with a different scene turns the job down. A tile goes out with the sums it already
+//! has, e.g. from a checkpoint, and comes back with all passes rendered, which gives the
+//! same image as rendering it locally.
+//!
+//! Workers send a heartbeat every second. One that stays silent for longer than the
+//! coordinator's timeout or loses its connection is given up on and the tiles it was
+//! rendering go to the other workers. Workers may connect at any time until the render is
+//! done, but a coordinator left without any for too long gives up and leaves the tiles
+//! still missing to be rendered otherwise.
+//!
+//! Every message is a tag byte, the length of the payload as a u32 and the payload, all
+//! numbers little endian.
+use crate::renderer::{tiles, Accumulation, NoObserver, PixelSums, RenderObserver, Renderer};
+use crate::renderer::{Tile, Tracker, DEFAULT_TILE_SIZE};
+use crate::scene::{RenderSettings, Scene, SceneError};
+use crate::vec3::Color;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const VERSION: u32 = 2;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the coordinator waits for a worker to send anything before giving up on it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the coordinator waits while no worker is connected before giving up
+pub const DEFAULT_WAIT: Duration = Duration::from_secs(60);
+/// How often the coordinator checks for new workers
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// Longest payload accepted, far more than a tile of sums takes
+const MAX_PAYLOAD: u32 = 64 << 20;
+/// Bytes of the sums of one pixel in a message
+const PIXEL_SIZE: usize = 4 * 8 + 4;
+
+/// Side length of the largest tiles a coordinator hands out, whose sums take 36 MiB
+pub const MAX_TILE_SIZE: usize = 1024;
+
+#[derive(Debug)]
+pub enum DistributedError {
+    Io(io::Error),
+    /// The other side sent something that does not fit the protocol
+    Protocol(&'static str),
+    /// The worker turned the job down
+    Refused(String),
+    /// The worker sent nothing for longer than the timeout
+    TimedOut,
+    /// The other side closed the connection
+    Disconnected,
+}
+
+impl fmt::Display for DistributedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DistributedError::Io(err) => write!(f, "{}", err),
+            DistributedError::Protocol(message) => write!(f, "protocol error, {}", message),
+            DistributedError::Refused(reason) => write!(f, "job refused, {}", reason),
+            DistributedError::TimedOut => write!(f, "timed out"),
+            DistributedError::Disconnected => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl Error for DistributedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DistributedError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DistributedError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DistributedError::TimedOut,
+            io::ErrorKind::UnexpectedEof => DistributedError::Disconnected,
+            _ => DistributedError::Io(err),
+        }
+    }
+}
+
+/// What workers render: the settings deciding the samples, and the scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub image_width: i32,
+    pub image_height: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples_per_pixel: i32,
+    pub roulette_depth: i32,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    /// Identifies the scene, see `fingerprint`
+    pub scene: u64,
+}
+
+impl Job {
+    pub fn new(settings: &RenderSettings, scene: u64) -> Job {
+        Job {
+            image_width: settings.image_width,
+            image_height: settings.image_height,
+            samples_per_pixel: settings.samples_per_pixel,
+            max_depth: settings.max_depth,
+            seed: settings.seed,
+            adaptive_threshold: settings.adaptive_threshold,
+            min_samples_per_pixel: settings.min_samples_per_pixel,
+            roulette_depth: settings.roulette_depth,
+            diffuse_depth: settings.diffuse_depth,
+            specular_depth: settings.specular_depth,
+            transmission_depth: settings.transmission_depth,
+            scene,
+        }
+    }
+
+    /// Overrides the settings the job decides
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        settings.image_width = self.image_width;
+        settings.image_height = self.image_height;
+        settings.samples_per_pixel = self.samples_per_pixel;
+        settings.max_depth = self.max_depth;
+        settings.seed = self.seed;
+        settings.adaptive_threshold = self.adaptive_threshold;
+        settings.min_samples_per_pixel = self.min_samples_per_pixel;
+        settings.roulette_depth = self.roulette_depth;
+        settings.diffuse_depth = self.diffuse_depth;
+        settings.specular_depth = self.specular_depth;
+        settings.transmission_depth = self.transmission_depth;
+    }
+
+    fn contains(&self, tile: &Tile) -> bool {
+        tile.width > 0
+            && tile.height > 0
+            && tile.x + tile.width <= self.image_width as usize
+            && tile.y + tile.height <= self.image_height as usize
+    }
+}
+
+/// FNV-1a hash of `bytes`, see `scene_fingerprint`
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Fingerprint of the scene file at `path` that `scene` was loaded from, together with the
+/// files it refers to, for workers to check they load the same scene as the coordinator.
+/// Only the contents count, not where the files are.
+pub fn scene_fingerprint(path: &Path, scene: &Scene) -> Result<u64, SceneError> {
+    let read = |path: &Path| {
+        fs::read(path).map_err(|source| SceneError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    };
+    let mut bytes = read(path)?;
+    for file in &scene.files {
+        bytes.extend_from_slice(&fingerprint(&read(file)?).to_le_bytes());
+    }
+    Ok(fingerprint(&bytes))
+}
+
+enum Message {
+    /// Coordinator to worker, first thing after connecting
+    Job(Job),
+    /// Worker to coordinator, takes the job and asks for up to `threads` tiles at a time
+    Ready {
+        threads: u32,
+    },
+    Refused(String),
+    /// Coordinator to worker, a tile to render and the sums it has so far
+    Render {
+        tile: Tile,
+        sums: Vec<PixelSums>,
+    },
+    /// Worker to coordinator, the sums of a rendered tile
+    Rendered {
+        tile: Tile,
+        samples: u64,
+        rays: u64,
+        sums: Vec<PixelSums>,
+    },
+    Heartbeat,
+    /// Coordinator to worker, all tiles are done
+    Done,
+}
+
+impl Message {
+    const JOB: u8 = 1;
+    const READY: u8 = 2;
+    const REFUSED: u8 = 3;
+    const RENDER: u8 = 4;
+    const RENDERED: u8 = 5;
+    const HEARTBEAT: u8 = 6;
+    const DONE: u8 = 7;
+
+    fn write(&self, mut stream: impl Write) -> io::Result<()> {
+        let mut payload = Payload::default();
+        let tag = match self {
+            Message::Job(job) => {
+                payload.u32(VERSION);
+                for value in &[
+                    job.image_width,
+                    job.image_height,
+                    job.samples_per_pixel,
+                    job.max_depth,
+                    job.min_samples_per_pixel,
+                    job.roulette_depth,
+                    // Limits are non-negative, -1 means none
+                    job.diffuse_depth.unwrap_or(-1),
+                    job.specular_depth.unwrap_or(-1),
+                    job.transmission_depth.unwrap_or(-1),
+                ] {
+                    payload.i32(*value);
+                }
+                payload.u64(job.seed);
+                // Thresholds are positive, 0 means no adaptive sampling
+                payload.f64(job.adaptive_threshold.unwrap_or(0.0));
+                payload.u64(job.scene);
+                Message::JOB
+            }
+            Message::Ready { threads } => {
+                payload.u32(*threads);
+                Message::READY
+            }
+            Message::Refused(reason) => {
+                payload.0.extend_from_slice(reason.as_bytes());
+                Message::REFUSED
+            }
+            Message::Render { tile, sums } => {
+                payload.tile(tile);
+                payload.sums(sums);
+                Message::RENDER
+            }
+            Message::Rendered {
+                tile,
+                samples,
+                rays,
+                sums,
+            } => {
+                payload.tile(tile);
+                payload.u64(*samples);
+                payload.u64(*rays);
+                payload.sums(sums);
+                Message::RENDERED
+            }
+            Message::Heartbeat => Message::HEARTBEAT,
+            Message::Done => Message::DONE,
+        };
+
+        let mut bytes = Vec::with_capacity(5 + payload.0.len());
+        bytes.push(tag);
+        bytes.extend_from_slice(&(payload.0.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&payload.0);
+        stream.write_all(&bytes)?;
+        stream.flush()
+    }
+
+    fn read(mut stream: impl Read) -> Result<Message, DistributedError> {
+        let mut header = [0; 5];
+        stream.read_exact(&mut header)?;
+        let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+        if length > MAX_PAYLOAD {
+            return Err(DistributedError::Protocol("message too long"));
+        }
+        let mut bytes = vec![0; length as usize];
+        stream.read_exact(&mut bytes)?;
+        let mut fields = Fields(&bytes);
+
+        let message = match header[0] {
+            Message::JOB => {
+                if fields.u32()? != VERSION {
+                    return Err(DistributedError::Protocol("unsupported version"));
+                }
+                let image_width = fields.i32()?;
+                let image_height = fields.i32()?;
+                let samples_per_pixel = fields.i32()?;
+                let max_depth = fields.i32()?;
+                let min_samples_per_pixel = fields.i32()?;
+                let roulette_depth = fields.i32()?;
+                let diffuse_depth = fields.depth_limit()?;
+                let specular_depth = fields.depth_limit()?;
+                let transmission_depth = fields.depth_limit()?;
+                Message::Job(Job {
+                    image_width,
+                    image_height,
+                    samples_per_pixel,
+                    max_depth,
+                    min_samples_per_pixel,
+                    roulette_depth,
+                    diffuse_depth,
+                    specular_depth,
+                    transmission_depth,
+                    seed: fields.u64()?,
+                    adaptive_threshold: Some(fields.f64()?).filter(|threshold| *threshold > 0.0),
+                    scene: fields.u64()?,
+                })
+            }
+            Message::READY => Message::Ready {
+                threads: fields.u32()?,
+            },
+            Message::REFUSED => {
+                let reason = String::from_utf8_lossy(fields.0).into_owned();
+                fields.0 = &[];
+                Message::Refused(reason)
+            }
+            Message::RENDER => {
+                let tile = fields.tile()?;
+                let sums = fields.sums(&tile)?;
+                Message::Render { tile, sums }
+            }
+            Message::RENDERED => {
+                let tile = fields.tile()?;
+                let samples = fields.u64()?;
+                let rays = fields.u64()?;
+                let sums = fields.sums(&tile)?;
+                Message::Rendered {
+                    tile,
+                    samples,
+                    rays,
+                    sums,
+                }
+            }
+            Message::HEARTBEAT => Message::Heartbeat,
+            Message::DONE => Message::Done,
+            _ => return Err(DistributedError::Protocol("unknown message")),
+        };
+        if !fields.0.is_empty() {
+            return Err(DistributedError::Protocol("message too long"));
+        }
+        Ok(message)
+    }
+}
+
+/// Payload of a message being written
+#[derive(Default)]
+struct Payload(Vec<u8>);
+
+impl Payload {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn tile(&mut self, tile: &Tile) {
+        for value in &[tile.x, tile.y, tile.width, tile.height] {
+            self.u32(*value as u32);
+        }
+    }
+
+    fn sums(&mut self, sums: &[PixelSums]) {
+        for sums in sums {
+            self.f64(sums.color.x);
+            self.f64(sums.color.y);
+            self.f64(sums.color.z);
+            self.f64(sums.luminance_squares);
+            self.i32(sums.samples);
+        }
+    }
+}
+
+/// Payload of a message being read
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DistributedError> {
+        if self.0.len() < N {
+            return Err(DistributedError::Protocol("message too short"));
+        }
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        let mut array = [0; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
+    }
+
+    fn u32(&mut self) -> Result<u32, DistributedError> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, DistributedError> {
+        self.bytes().map(i32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, DistributedError> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn depth_limit(&mut self) -> Result<Option<i32>, DistributedError> {
+        self.i32()
+            .map(|depth| Some(depth).filter(|depth| *depth >= 0))
+    }
+
+    fn f64(&mut self) -> Result<f64, DistributedError> {
+        self.bytes().map(f64::from_le_bytes)
+    }
+
+    fn tile(&mut self) -> Result<Tile, DistributedError> {
+        Ok(Tile {
+            x: self.u32()? as usize,
+            y: self.u32()? as usize,
+            width: self.u32()? as usize,
+            height: self.u32()? as usize,
+        })
+    }
+
+    /// The sums of every pixel of `tile`
+    fn sums(&mut self, tile: &Tile) -> Result<Vec<PixelSums>, DistributedError> {
+        let pixels = tile.width * tile.height;
+        if self.0.len() != pixels * PIXEL_SIZE {
+            return Err(DistributedError::Protocol("wrong number of pixels"));
+        }
+        (0..pixels)
+            .map(|_| {
+                let color = Color::new(self.f64()?, self.f64()?, self.f64()?);
+                Ok(PixelSums {
+                    color,
+                    luminance_squares: self.f64()?,
+                    samples: self.i32()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Hands out the tiles of a job to the workers connecting to `listener`
+pub struct Coordinator<'a> {
+    listener: TcpListener,
+    job: Job,
+    observer: &'a dyn RenderObserver,
+    timeout: Duration,
+    wait: Duration,
+    tile_size: usize,
+}
+
+/// State of a distributed render shared by the threads talking to the workers
+struct Shared<'a> {
+    queue: Mutex<Queue>,
+    /// Signalled when tiles are put back in the queue and when the last one is done
+    changed: Condvar,
+    accumulation: Mutex<&'a mut Accumulation>,
+    tracker: Tracker,
+    tiles: usize,
+}
+
+struct Queue {
+    pending: VecDeque<Tile>,
+    /// Tiles not merged yet, pending or out at a worker
+    remaining: usize,
+}
+
+impl Shared<'_> {
+    fn is_finished(&self) -> bool {
+        self.queue.lock().unwrap().remaining == 0
+    }
+
+    /// Takes up to `count` pending tiles. With `wait`, waits for at least one unless the
+    /// render is done. `None` once it is.
+    fn assign(&self, count: usize, wait: bool) -> Option<Vec<Tile>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.remaining == 0 {
+                return None;
+            }
+            let count = count.min(queue.pending.len());
+            if count > 0 || !wait {
+                return Some(queue.pending.drain(..count).collect());
+            }
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+
+    /// Puts back the tiles of a worker that was given up on
+    fn give_back(&self, tiles: Vec<Tile>) {
+        self.queue.lock().unwrap().pending.extend(tiles);
+        self.changed.notify_all();
+    }
+
+    fn merge(&self, observer: &dyn RenderObserver, tile: &Tile, sums: &[PixelSums]) {
+        let mut accumulation = self.accumulation.lock().unwrap();
+        accumulation.set_tile(tile, sums);
+        observer.tile_merged(tile, &accumulation);
+        drop(accumulation);
+
+        let mut queue = self.queue.lock().unwrap();
+        queue.remaining -= 1;
+        if queue.remaining == 0 {
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl<'a> Coordinator<'a> {
+    pub fn new(listener: TcpListener, job: Job) -> Coordinator<'a> {
+        Coordinator {
+            listener,
+            job,
+            observer: &NoObserver,
+            timeout: DEFAULT_TIMEOUT,
+            wait: DEFAULT_WAIT,
+            tile_size: DEFAULT_TILE_SIZE,
+        }
+    }
+
+    /// Gets told about tiles and workers. Tile events come as with `Renderer`, all tiles
+    /// are in a single pass.
+    pub fn with_observer(mut self, observer: &'a dyn RenderObserver) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Gives up on workers that send nothing for `timeout`, which has to be longer than a
+    /// second
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gives up on the render once no worker was connected for `wait`
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Hands out square tiles of `tile_size` pixels a side, at most `MAX_TILE_SIZE` so
+    /// their sums fit in a message
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        assert!(tile_size > 0, "tiles need at least one pixel");
+        self.tile_size = tile_size.min(MAX_TILE_SIZE);
+        self
+    }
+
+    /// Renders the job into `accumulation`, whose size has to match it, with the workers
+    /// that connect, until all tiles are back. Fails with `ErrorKind::TimedOut` once no
+    /// worker was connected for the wait, see `with_wait`, `accumulation` then holds the
+    /// tiles that came back until then.
+    pub fn render(&self, accumulation: &mut Accumulation) -> io::Result<()> {
+        let tiles = tiles(accumulation.width, accumulation.height, self.tile_size);
+        let shared = Shared {
+            queue: Mutex::new(Queue {
+                pending: tiles.iter().copied().collect(),
+                remaining: tiles.len(),
+            }),
+            changed: Condvar::new(),
+            tracker: Tracker::new(accumulation, self.job.samples_per_pixel),
+            accumulation: Mutex::new(&mut *accumulation),
+            tiles: tiles.len(),
+        };
+
+        self.observer.pass_started(0, 1, self.job.samples_per_pixel);
+        self.listener.set_nonblocking(true)?;
+        let workers = AtomicUsize::new(0);
+        let gave_up = thread::scope(|scope| {
+            let mut idle_since = Some(Instant::now());
+            while !shared.is_finished() {
+                match self.listener.accept() {
+                    Ok((stream, address)) => {
+                        workers.fetch_add(1, Ordering::SeqCst);
+                        let (shared, workers) = (&shared, &workers);
+                        scope.spawn(move || {
+                            self.observer.worker_connected(address);
+                            let result = self.serve(stream, shared);
+                            self.observer
+                                .worker_disconnected(address, result.as_ref().err());
+                            workers.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                    // Nobody waiting, or a connection that failed before it was accepted
+                    Err(_) => thread::sleep(ACCEPT_INTERVAL),
+                }
+
+                if workers.load(Ordering::SeqCst) > 0 {
+                    idle_since = None;
+                } else if idle_since.get_or_insert_with(Instant::now).elapsed() >= self.wait {
+                    return true;
+                }
+            }
+            false
+        });
+        drop(shared);
+        if gave_up {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no worker connected for {}s", self.wait.as_secs()),
+            ));
+        }
+
+        self.observer.pass_finished(0, 1, accumulation);
+        Ok(())
+    }
+
+    /// Talks to one worker until the render is done or the worker is given up on
+    fn serve(&self, mut stream: TcpStream, shared: &Shared) -> Result<(), DistributedError> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+
+        Message::Job(self.job.clone()).write(&stream)?;
+        let threads = match Message::read(&stream)? {
+            Message::Ready { threads } => threads.max(1) as usize,
+            Message::Refused(reason) => return Err(DistributedError::Refused(reason)),
+            _ => return Err(DistributedError::Protocol("expected an answer to the job")),
+        };
+
+        let mut in_flight = Vec::new();
+        let result = self.exchange(&mut stream, shared, threads, &mut in_flight);
+        if !in_flight.is_empty() {
+            shared.give_back(in_flight);
+        }
+        result
+    }
+
+    /// Keeps up to `threads` tiles out at the worker, `in_flight`, and merges what comes
+    /// back
+    fn exchange(
+        &self,
+        stream: &mut TcpStream,
+        shared: &Shared,
+        threads: usize,
+        in_flight: &mut Vec<Tile>,
+    ) -> Result<(), DistributedError> {
+        loop {
+            let assigned = match shared.assign(threads - in_flight.len(), in_flight.is_empty()) {
+                Some(assigned) => assigned,
+                None => return Ok(Message::Done.write(&*stream)?),
+            };
+            for tile in assigned {
+                in_flight.push(tile);
+                let sums = shared.accumulation.lock().unwrap().tile(&tile);
+                Message::Render { tile, sums }.write(&*stream)?;
+                self.observer.tile_started(&tile);
+            }
+
+            match Message::read(&*stream)? {
+                Message::Heartbeat => {}
+                Message::Rendered {
+                    tile,
+                    samples,
+                    rays,
+                    sums,
+                } => {
+                    let index = in_flight
+                        .iter()
+                        .position(|assigned| *assigned == tile)
+                        .ok_or(DistributedError::Protocol("result of a tile not assigned"))?;
+                    in_flight.swap_remove(index);
+                    shared.merge(self.observer, &tile, &sums);
+                    shared.tracker.add_tile(samples, rays);
+                    self.observer
+                        .tile_finished(&tile, &shared.tracker.progress(shared.tiles));
+                }
+                _ => return Err(DistributedError::Protocol("unexpected message")),
+            }
+        }
+    }
+}
+
+/// Renders tiles for a coordinator
+pub struct Worker {
+    stream: TcpStream,
+    job: Job,
+}
+
+impl Worker {
+    /// Connects to the coordinator at `address` and receives the job
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Worker, DistributedError> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let job = match Message::read(&stream)? {
+            Message::Job(job) => job,
+            _ => return Err(DistributedError::Protocol("expected a job")),
+        };
+        Ok(Worker { stream, job })
+    }
+
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    /// Turns the job down, e.g. because the scene differs
+    pub fn refuse(self, reason: &str) -> Result<(), DistributedError> {
+        Ok(Message::Refused(reason.to_string()).write(&self.stream)?)
+    }
+
+    /// Renders the tiles the coordinator sends with `renderer`, which has to be set up with
+    /// the settings of the job, until the coordinator is done. Tiles are rendered in
+    /// parallel on as many threads as the rayon thread pool has.
+    pub fn serve(self, renderer: &Renderer) -> Result<(), DistributedError> {
+        let Worker {
+            stream: reader,
+            job,
+        } = self;
+        let writer = Mutex::new(reader.try_clone()?);
+        let send = |message: Message| message.write(&*writer.lock().unwrap());
+
+        let threads = rayon::current_num_threads();
+        send(Message::Ready {
+            threads: threads as u32,
+        })?;
+
+        let done = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel::<(Tile, Vec<PixelSums>)>();
+        let receiver = Mutex::new(receiver);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    thread::sleep(HEARTBEAT_INTERVAL);
+                    if send(Message::Heartbeat).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let rendering: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| loop {
+                        let next = receiver.lock().unwrap().recv();
+                        let (tile, mut sums) = match next {
+                            Ok(next) => next,
+                            Err(_) => return Ok::<_, io::Error>(()),
+                        };
+                        let (samples, rays) = renderer.render_tile(&tile, &mut sums);
+                        send(Message::Rendered {
+                            tile,
+                            samples,
+                            rays,
+                            sums,
+                        })?;
+                    })
+                })
+                .collect();
+
+            let read = loop {
+                match Message::read(&reader) {
+                    Ok(Message::Render { tile, sums }) if job.contains(&tile) => {
+                        // Only fails once all render threads stopped after failing to send
+                        sender.send((tile, sums)).ok();
+                    }
+                    Ok(Message::Render { .. }) => {
+                        break Err(DistributedError::Protocol("tile outside of the image"))
+                    }
+                    Ok(Message::Done) => break Ok(()),
+                    Ok(_) => break Err(DistributedError::Protocol("unexpected message")),
+                    Err(err) => break Err(err),
+                }
+            };
+            drop(sender);
+
+            let rendered = rendering
+                .into_iter()
+                .try_for_each(|thread| thread.join().unwrap());
+            done.store(true, Ordering::Relaxed);
+            read.and(rendered.map_err(DistributedError::from))
+        })
+    }
+}
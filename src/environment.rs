@@ -0,0 +1,263 @@
+use crate::color::luminance;
+use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vec3::{Color, Vec3};
+use image::ImageError;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Radiance of rays that escape the scene
+#[derive(Clone, Default)]
+pub enum Environment {
+    /// White to light blue gradient along the vertical axis
+    #[default]
+    Sky,
+    Solid(Color),
+    Map(Arc<EnvironmentMap>),
+}
+
+impl Environment {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Environment::Sky => {
+                let unit_direction = ray.direction.normalize();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+            }
+            Environment::Solid(color) => *color,
+            Environment::Map(map) => map.radiance(ray.direction),
+        }
+    }
+
+    /// Whether light sampling should pick directions towards the environment, only worth it
+    /// for maps, which usually have small bright spots like the sun
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Environment::Map(map) if map.total > 0.0)
+    }
+
+    /// Picks a direction by the environment's brightness from uniform numbers `u` in [0, 1),
+    /// returns it with its solid angle density
+    pub fn sample(&self, u: [f64; 2]) -> Option<(Vec3, f64)> {
+        match self {
+            Environment::Map(map) => map.sample(u),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density with which `sample` picks `direction`
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Piecewise constant density over [0, 1), sampled by inverting its cumulative sum
+struct Distribution {
+    /// Normalized running sum of the weights, starting with 0 and ending with 1
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution {
+    fn new(weights: &[f64]) -> Distribution {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut total = 0.0;
+        cdf.push(0.0);
+        for weight in weights {
+            total += weight;
+            cdf.push(total);
+        }
+        if total > 0.0 {
+            for value in &mut cdf {
+                *value /= total;
+            }
+        }
+        Distribution { cdf, total }
+    }
+
+    /// Returns the picked piece and where `u` fell inside it, in [0, 1)
+    fn sample(&self, u: f64) -> (usize, f64) {
+        // Last piece whose cumulative sum starts at or before u, skipping empty ones
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .clamp(1, self.cdf.len() - 1)
+            - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[index]) / width).clamp(0.0, 1.0 - f64::EPSILON)
+        } else {
+            0.0
+        };
+        (index, offset)
+    }
+
+    /// Probability of picking piece `index`
+    fn probability(&self, index: usize) -> f64 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+}
+
+/// Equirectangular image of the radiance arriving from every direction. The image's top
+/// row is straight up and `u` grows around the vertical axis as on spheres.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// Radiance, row by row from the top
+    pixels: Vec<Color>,
+    /// World to map directions
+    rotation: Transform,
+    /// Picks a row, then `columns` the pixel in it, both by luminance
+    rows: Distribution,
+    columns: Vec<Distribution>,
+    total: f64,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance HDR or OpenEXR file, whose pixels are linear radiance. `rotation`
+    /// turns the map around the vertical axis in degrees, `intensity` scales it.
+    pub fn open(
+        path: impl AsRef<Path>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<EnvironmentMap, ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels: Vec<Color> = image
+            .pixels()
+            .map(|p| intensity * Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        Ok(EnvironmentMap::from_pixels(width, height, pixels, rotation))
+    }
+
+    /// Map of `pixels`, row by row from the top, turned by `rotation` degrees
+    fn from_pixels(width: usize, height: usize, pixels: Vec<Color>, rotation: f64) -> Self {
+        // Rows near the poles cover less solid angle than the ones at the horizon
+        let weights: Vec<Vec<f64>> = (0..height)
+            .map(|j| {
+                let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+                pixels[j * width..(j + 1) * width]
+                    .iter()
+                    .map(|c| luminance(*c).max(0.0) * sin_theta)
+                    .collect()
+            })
+            .collect();
+        let columns: Vec<Distribution> = weights.iter().map(|row| Distribution::new(row)).collect();
+        let rows = Distribution::new(&columns.iter().map(|c| c.total).collect::<Vec<_>>());
+        let total = rows.total;
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: Transform::rotate(Vec3::new(0.0, 1.0, 0.0), -rotation),
+            rows,
+            columns,
+            total,
+        }
+    }
+
+    /// Pixel seen in `direction`
+    fn pixel(&self, direction: Vec3) -> (usize, usize) {
+        let d = self.rotation.vector(direction).normalize();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI;
+        let i = ((phi / (2.0 * PI) * self.width as f64) as usize).min(self.width - 1);
+        let j = ((theta / PI * self.height as f64) as usize).min(self.height - 1);
+        (i, j)
+    }
+
+    fn radiance(&self, direction: Vec3) -> Color {
+        if self.pixels.is_empty() {
+            return Color::default();
+        }
+        let (i, j) = self.pixel(direction);
+        self.pixels[j * self.width + i]
+    }
+
+    fn sample(&self, u: [f64; 2]) -> Option<(Vec3, f64)> {
+        if self.total <= 0.0 {
+            return None;
+        }
+        let (j, dv) = self.rows.sample(u[0]);
+        let (i, du) = self.columns[j].sample(u[1]);
+
+        let theta = PI * (j as f64 + dv) / self.height as f64;
+        let phi = 2.0 * PI * (i as f64 + du) / self.width as f64 - PI;
+        let sin_theta = theta.sin();
+        let local = Vec3::new(sin_theta * phi.cos(), theta.cos(), -sin_theta * phi.sin());
+        let direction = self.rotation.inverse().vector(local);
+
+        let pdf = self.pixel_pdf(i, j, sin_theta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((direction, pdf))
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        if self.total <= 0.0 {
+            return 0.0;
+        }
+        let (i, j) = self.pixel(direction);
+        let d = direction.normalize();
+        let sin_theta = (1.0 - d.y * d.y).max(0.0).sqrt();
+        self.pixel_pdf(i, j, sin_theta)
+    }
+
+    /// Solid angle density of a direction inside pixel (i, j) at polar angle theta. Pixels
+    /// are sampled uniformly in the image, which stretches them by 2 pi^2 sin(theta) on the
+    /// sphere.
+    fn pixel_pdf(&self, i: usize, j: usize, sin_theta: f64) -> f64 {
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let probability = self.rows.probability(j) * self.columns[j].probability(i);
+        probability * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chi_square::assert_samples_match;
+    use rand::Rng;
+
+    #[test]
+    fn samples_follow_the_density() {
+        // A bright spot, a dark band and a few black pixels the sampler has to skip
+        let (width, height) = (8, 4);
+        let pixels = (0..width * height)
+            .map(|index| match (index % width, index / width) {
+                (2, 1) => Color::new(20.0, 15.0, 10.0),
+                (_, 3) => Color::new(0.1, 0.1, 0.1),
+                (5, _) | (6, _) => Color::default(),
+                (i, j) => Color::new(1.0 + i as f64, 0.5, 0.2 * j as f64),
+            })
+            .collect();
+        let environment = Environment::Map(Arc::new(EnvironmentMap::from_pixels(
+            width, height, pixels, 30.0,
+        )));
+        assert!(environment.is_sampled());
+
+        assert_samples_match(
+            "environment map",
+            100_000,
+            |rng| {
+                let (direction, pdf) = environment.sample([rng.gen(), rng.gen()])?;
+                let expected = environment.pdf(direction);
+                assert!(
+                    (pdf - expected).abs() <= 1.0e-6 * expected,
+                    "sampled density {}, expected {}",
+                    pdf,
+                    expected
+                );
+                Some(direction)
+            },
+            |direction| environment.pdf(direction),
+        );
+    }
+}
@@ -1,17 +1,84 @@
+use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
 
 pub struct HitRecord<'world> {
     pub p: Point3,
     pub normal: Vec3,
     pub material: Option<&'world dyn Material>,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Solid angle density, as seen from `origin`, with which `random` picks `direction`
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Direction from `origin` towards a random point on the object, used to sample lights
+    fn random(&self, _origin: Point3, _sampler: &mut Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+macro_rules! impl_hittable_for_pointer {
+    ($pointer: ident) => {
+        impl<T: Hittable + ?Sized> Hittable for $pointer<T> {
+            fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+                self.as_ref().hit(ray, t_min, t_max)
+            }
+
+            fn bounding_box(&self) -> Option<Aabb> {
+                self.as_ref().bounding_box()
+            }
+
+            fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+                self.as_ref().pdf_value(origin, direction)
+            }
+
+            fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+                self.as_ref().random(origin, sampler)
+            }
+        }
+    };
+}
+
+impl_hittable_for_pointer!(Box);
+impl_hittable_for_pointer!(Arc);
+
+/// Turns the outward normal of the wrapped object around, e.g. to make a one-sided light
+/// shine the other way
+pub struct Flipped<T>(pub T);
+
+impl<T: Hittable> Hittable for Flipped<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.0.hit(ray, t_min, t_max)?;
+        rec.front_face = !rec.front_face;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.0.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.0.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        self.0.random(origin, sampler)
+    }
 }
 
 impl<'world> HitRecord<'world> {
@@ -21,6 +88,8 @@ impl<'world> HitRecord<'world> {
             normal: p,
             material,
             t,
+            u: 0.0,
+            v: 0.0,
             front_face: false,
         }
     }
@@ -33,4 +102,9 @@ impl<'world> HitRecord<'world> {
             -outward_normal
         };
     }
+
+    /// Local frame materials work in, its z axis is the normal on the side of the ray
+    pub fn shading_frame(&self) -> Onb {
+        Onb::from_w(self.normal)
+    }
 }
@@ -1,5 +1,9 @@
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use rand::Rng;
 
 pub struct HittableList {
     objects: Vec<Box<dyn Hittable + Sync + Send>>,
@@ -13,9 +17,7 @@ impl HittableList {
     }
 
     pub fn from_hittable(object: impl Hittable + Sync + Send + 'static) -> HittableList {
-        let mut list = HittableList {
-            objects: Vec::new(),
-        };
+        let mut list = HittableList::new();
         list.add(object);
         list
     }
@@ -27,10 +29,28 @@ impl HittableList {
     pub fn add(&mut self, object: impl Hittable + Sync + Send + 'static) {
         self.objects.push(Box::new(object))
     }
+
+    pub fn append(&mut self, other: HittableList) {
+        self.objects.extend(other.objects)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable + Sync + Send>> {
+        self.objects
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        HittableList::new()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut temp_rec: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
@@ -43,4 +63,33 @@ impl Hittable for HittableList {
 
         temp_rec
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut output_box: Option<Aabb> = None;
+
+        for object in &self.objects {
+            let object_box = object.bounding_box()?;
+            output_box = Some(match output_box {
+                Some(b) => Aabb::surrounding_box(b, object_box),
+                None => object_box,
+            });
+        }
+
+        output_box
+    }
+
+    /// Picks one of the objects uniformly, the density is the average of theirs
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        let index = ((sampler.gen::<f64>() * self.objects.len() as f64) as usize)
+            .min(self.objects.len() - 1);
+        self.objects[index].random(origin, sampler)
+    }
 }
@@ -0,0 +1,209 @@
+//! Light transport: the radiance arriving along camera rays, estimated by path tracing with
+//! light sampling and multiple importance sampling
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Lobe;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::{RenderSettings, Scene};
+use crate::vec3::{Color, Point3, Vec3};
+use rand::Rng;
+
+/// Multiple importance sampling weight of a sample drawn with density `pdf_f`
+fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+    let f2 = pdf_f * pdf_f;
+    f2 / (f2 + pdf_g * pdf_g)
+}
+
+/// Whether light sampling has anything to aim for
+fn has_lights(scene: &Scene) -> bool {
+    !scene.lights.is_empty() || scene.environment.is_sampled()
+}
+
+/// Density with which `sample_light` picks `direction`, out of the lights and the
+/// environment together
+fn light_pdf(scene: &Scene, origin: Point3, direction: Vec3) -> f64 {
+    match (scene.lights.is_empty(), scene.environment.is_sampled()) {
+        (false, true) => {
+            0.5 * (scene.lights.pdf_value(origin, direction) + scene.environment.pdf(direction))
+        }
+        (false, false) => scene.lights.pdf_value(origin, direction),
+        (true, true) => scene.environment.pdf(direction),
+        (true, false) => 0.0,
+    }
+}
+
+/// Direct light arriving at `hit_record` from one light sampled out of `scene.lights` or
+/// from the environment, `wo` is the direction back along the incoming ray in the shading
+/// frame. Unless the path `may_scatter` on, no BSDF sampled ray finds the light from here,
+/// so the light sample takes all of it rather than its multiple importance sampling share.
+/// Counts the shadow ray in `rays`.
+fn sample_light(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    wo: Vec3,
+    may_scatter: bool,
+    scene: &Scene,
+    sampler: &mut Sampler,
+    rays: &mut u64,
+) -> Color {
+    let material = hit_record.material.unwrap();
+    let frame = hit_record.shading_frame();
+    let towards_environment =
+        scene.environment.is_sampled() && (scene.lights.is_empty() || sampler.gen::<f64>() < 0.5);
+    let direction = if towards_environment {
+        match scene.environment.sample([sampler.gen(), sampler.gen()]) {
+            Some((direction, _)) => direction,
+            None => return Color::default(),
+        }
+    } else {
+        scene.lights.random(hit_record.p, sampler)
+    };
+    let light_ray = Ray::new(hit_record.p, direction, ray.time).with_medium_seed(sampler.gen());
+    let light_pdf = light_pdf(scene, light_ray.origin, light_ray.direction);
+    if light_pdf <= 0.0 {
+        return Color::default();
+    }
+
+    let wi = frame.world_to_local(light_ray.direction.normalize());
+    let f = material.eval(hit_record, wo, wi);
+    if f.near_zero() {
+        return Color::default();
+    }
+
+    // Shadow ray, whatever emitter it reaches first is the one that lights the point
+    *rays += 1;
+    let emitted = match scene.world.hit(&light_ray, 0.001, f64::INFINITY) {
+        Some(light_hit) => light_hit.material.unwrap().emitted(&light_hit),
+        None if scene.environment.is_sampled() => scene.environment.color(&light_ray),
+        None => return Color::default(),
+    };
+    let weight = if may_scatter {
+        power_heuristic(light_pdf, material.pdf(hit_record, wo, wi))
+    } else {
+        1.0
+    };
+    weight * f * emitted / light_pdf
+}
+
+/// Bounces of each kind a path took so far
+#[derive(Default)]
+struct Bounces {
+    diffuse: i32,
+    specular: i32,
+    transmission: i32,
+}
+
+impl Bounces {
+    /// Counts a bounce off `lobe`, false once that takes the path past the limit of its
+    /// kind. Diffuse lobes count as diffuse whichever side they scatter to, other lobes
+    /// count as transmission through the surface or as specular, glossy ones included, off
+    /// it.
+    fn add(&mut self, lobe: Lobe, settings: &RenderSettings) -> bool {
+        let (count, limit) = if lobe.contains(Lobe::DIFFUSE) {
+            (&mut self.diffuse, settings.diffuse_depth)
+        } else if lobe.contains(Lobe::TRANSMISSION) {
+            (&mut self.transmission, settings.transmission_depth)
+        } else {
+            (&mut self.specular, settings.specular_depth)
+        };
+        *count += 1;
+        limit.is_none_or(|limit| *count <= limit)
+    }
+}
+
+/// Radiance arriving along `ray`. A path ends after `settings.max_depth` rays or once it
+/// bounces more often than the depth limit of a kind of bounce allows. After
+/// `settings.roulette_depth` bounces Russian roulette ends paths with a probability that
+/// grows as their throughput drops, and weights the ones that go on up to keep the estimate
+/// unbiased. Every ray traced against the scene is counted in `rays`.
+pub fn ray_color(
+    mut ray: Ray,
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: &mut Sampler,
+    rays: &mut u64,
+) -> Color {
+    let mut color = Color::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    // Density with which the previous bounce picked `ray`, `None` for camera rays and
+    // perfectly specular bounces, which light sampling cannot produce
+    let mut bsdf_pdf = None;
+    let mut bounces = Bounces::default();
+
+    for depth in 0..settings.max_depth {
+        ray.medium_seed = sampler.gen();
+        *rays += 1;
+        let hit_record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit_record) => hit_record,
+            None => {
+                let environment = scene.environment.color(&ray);
+                let weight = match bsdf_pdf {
+                    // Sampled environments were already sampled directly at the previous vertex
+                    Some(bsdf_pdf) if scene.environment.is_sampled() => {
+                        let light_pdf = light_pdf(scene, ray.origin, ray.direction);
+                        power_heuristic(bsdf_pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                color += throughput * weight * environment;
+                break;
+            }
+        };
+
+        let material = hit_record.material.unwrap();
+        let mut emitted = material.emitted(&hit_record);
+        // Emitters were already sampled directly at the previous vertex
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if !emitted.near_zero() {
+                let light_pdf = light_pdf(scene, ray.origin, ray.direction);
+                emitted *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+        color += throughput * emitted;
+
+        let frame = hit_record.shading_frame();
+        let wo = frame.world_to_local(-ray.direction.normalize());
+
+        let u = [sampler.gen(), sampler.gen(), sampler.gen()];
+        let sample = material.sample(&hit_record, wo, u);
+        // Paths end after the last ray and at bounces past the limit of their kind. Samples
+        // the material has none for also end them, but they lie outside its density, which
+        // the light sample below is weighted against.
+        let may_scatter = depth + 1 < settings.max_depth
+            && sample
+                .as_ref()
+                .is_none_or(|sample| bounces.add(sample.lobe, settings));
+
+        // Scattering inside a medium arrives here too, as a hit whose material is the phase
+        // function, so volumes get the same light sampling as surfaces. Whether to sample
+        // lights depends on the material alone, not on the lobe sampled above, or mixed
+        // materials would only get their direct light part of the time.
+        if has_lights(scene) && !material.is_specular(&hit_record, wo) {
+            color +=
+                throughput * sample_light(&ray, &hit_record, wo, may_scatter, scene, sampler, rays);
+        }
+
+        let sample = match sample {
+            Some(sample) if may_scatter => sample,
+            _ => break,
+        };
+        bsdf_pdf = if sample.lobe.contains(Lobe::SPECULAR) {
+            None
+        } else {
+            Some(sample.pdf)
+        };
+        throughput = throughput * sample.weight;
+
+        if depth + 1 >= settings.roulette_depth {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+            if sampler.gen::<f64>() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        ray = Ray::new(hit_record.p, frame.local(sample.wi), ray.time);
+    }
+
+    color
+}
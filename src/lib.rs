@@ -0,0 +1,37 @@
+//! Path tracer after "Ray Tracing in One Weekend" and its sequels. Scenes are loaded with
+//! `scene::load_scene` or assembled in code, and `Renderer` turns one into an image.
+pub mod aabb;
+pub mod aarect;
+pub mod box_shape;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+#[cfg(test)]
+mod chi_square;
+pub mod color;
+pub mod constant_medium;
+pub mod distributed;
+pub mod environment;
+pub mod hittable;
+pub mod hittable_list;
+pub mod integrator;
+pub mod material;
+pub mod mesh;
+pub mod microfacet;
+pub mod moving_sphere;
+pub mod obj;
+pub mod onb;
+pub mod output;
+pub mod perlin;
+pub mod plane;
+pub mod ray;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod sphere;
+pub mod texture;
+pub mod transform;
+pub mod triangle;
+pub mod vec3;
+
+pub use renderer::Renderer;
@@ -1,26 +1,28 @@
-mod camera;
-mod color;
-mod hittable;
-mod hittable_list;
-mod material;
-mod ray;
-mod sphere;
-mod vec3;
-
-use camera::Camera;
-use color::write_color;
-use hittable::Hittable;
-use hittable_list::HittableList;
-use material::{Dielectric, Lambertian, Metal};
-use rand::Rng;
-use ray::Ray;
-use rayon::prelude::*;
-use sphere::Sphere;
+mod cli;
+
+use rand::{rngs::StdRng, SeedableRng};
+use raytracing::checkpoint;
+use raytracing::distributed::{self, Coordinator, DistributedError, Job, Worker};
+use raytracing::environment::Environment;
+use raytracing::hittable_list::HittableList;
+use raytracing::material::{Dielectric, Lambertian, Metal};
+use raytracing::output::{self, BitDepth, Framebuffer, ImageEncoder, PpmAscii};
+use raytracing::renderer::{
+    self, Accumulation, CancelToken, Progress, RenderObserver, RenderStatus, Tile,
+};
+use raytracing::scene::{self, build_world, CameraSettings, RenderSettings, Scene};
+use raytracing::sphere::Sphere;
+use raytracing::vec3::{Color, Point3, Vec3};
+use raytracing::Renderer;
 use std::{
-    io::{stderr, Write},
-    sync::Arc,
+    fs::{self, OpenOptions},
+    io::{self, stderr, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::atomic::{AtomicI32, Ordering},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
-use vec3::{Color, Point3, Vec3};
 
 fn random_scene(rng: &mut impl rand::Rng) -> HittableList {
     let mut world = HittableList::new();
@@ -74,150 +76,470 @@ fn random_scene(rng: &mut impl rand::Rng) -> HittableList {
     world
 }
 
-fn hit_sphere(center: Point3, radius: f64, ray: &Ray) -> f64 {
-    let oc = ray.origin - center;
-    let a = ray.direction.length_squared();
-    let half_b = oc.dot(ray.direction);
-    let c = oc.length_squared() - radius * radius;
-    let discriminant = half_b * half_b - a * c;
-    if discriminant < 0.0 {
-        -1.0
-    } else {
-        (-half_b - discriminant.sqrt()) / a
+/// The scene rendered when no scene file is given
+fn default_scene(rng: &mut impl rand::Rng) -> Scene {
+    let settings = RenderSettings::default();
+
+    let lookfrom = Point3::new(13.0, 2.0, 3.0);
+    let lookat = Point3::new(0.0, 0.0, 0.0);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.1;
+
+    let camera = CameraSettings {
+        lookfrom,
+        lookat,
+        vup,
+        vfov: 20.0,
+        aperture,
+        focus_dist: dist_to_focus,
+        shutter_open: 0.0,
+        shutter_close: 0.0,
+    };
+
+    Scene {
+        world: build_world(random_scene(rng), settings.split_method),
+        lights: HittableList::new(),
+        environment: Environment::Sky,
+        camera,
+        settings,
+        files: Vec::new(),
     }
 }
 
-fn ray_color(ray: &Ray, world: &impl Hittable, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::default();
+fn main() {
+    let options = cli::parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        if let cli::CliError::HelpRequested = err {
+            println!("{}", cli::USAGE);
+            std::process::exit(0)
+        }
+        eprintln!("Error: {}\n\n{}", err, cli::USAGE);
+        std::process::exit(2)
+    });
+
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to configure the render threads");
     }
 
-    if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-        if let Some((attenuation, scattered)) = hit_record
-            .material
-            .as_ref()
-            .unwrap()
-            .scatter(&ray, &hit_record)
-        {
-            return attenuation * ray_color(&scattered, world, depth - 1);
-        } else {
-            return Color::default();
+    if let Some(address) = &options.worker {
+        run_worker(address, options.scene.as_deref());
+        return;
+    }
+
+    // No path or '-' means stdout
+    let output_path = options
+        .output
+        .clone()
+        .filter(|path| path.as_os_str() != "-");
+    let encoder: Box<dyn ImageEncoder> = match &output_path {
+        Some(path) => output::encoder_for_path(path, options.bit_depth.unwrap_or(BitDepth::Eight))
+            .unwrap_or_else(|err| {
+                eprintln!("Error: {}", err);
+                std::process::exit(2)
+            }),
+        None => Box::new(PpmAscii),
+    };
+    if let Some(path) = &output_path {
+        if let Err(err) = check_writable(path) {
+            eprintln!("Error creating {}: {}", path.display(), err);
+            std::process::exit(1)
         }
+    } else if options.progressive.is_some() && options.serve.is_none() {
+        eprintln!(
+            "Error: '--progressive' rewrites the image and needs an --output file\n\n{}",
+            cli::USAGE
+        );
+        std::process::exit(2)
     }
-    let unit_direction = ray.direction.normalize();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
-}
 
-fn main() {
-    // TODO: error handling
-    let stdout = std::io::stdout();
-    let mut handle = stdout.lock();
+    if options.progressive.is_some() && options.serve.is_some() {
+        eprintln!(
+            "Error: '--progressive' renders locally, it cannot be combined with '--serve'\n\n{}",
+            cli::USAGE
+        );
+        std::process::exit(2)
+    }
 
-    // Image
-    const ASPECT_RATIO: f64 = 3.0 / 2.0;
-    const IMAGE_WIDTH: i32 = 1200;
-    const IMAGE_HEIGHT: i32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as i32;
-    const NUM_PIXELS: i32 = IMAGE_WIDTH * IMAGE_HEIGHT;
-    const SAMPLES_PER_PIXEL: i32 = 500;
-    const MAX_DEPTH: i32 = 500;
+    let sample_map_encoder = options.sample_map.as_ref().map(|path| {
+        output::encoder_for_path(path, options.bit_depth.unwrap_or(BitDepth::Eight)).unwrap_or_else(
+            |err| {
+                eprintln!("Error: {}", err);
+                std::process::exit(2)
+            },
+        )
+    });
+
+    let checkpoint = match (&options.checkpoint, options.resume) {
+        (Some(path), true) => Some(checkpoint::load(path).unwrap_or_else(|err| {
+            eprintln!("Error loading checkpoint {}: {}", path.display(), err);
+            std::process::exit(1)
+        })),
+        (None, true) => {
+            eprintln!(
+                "Error: '--resume' needs the --checkpoint to resume from\n\n{}",
+                cli::USAGE
+            );
+            std::process::exit(2)
+        }
+        (_, false) => None,
+    };
+
+    let seed = options
+        .seed
+        .or_else(|| checkpoint.as_ref().map(|checkpoint| checkpoint.seed));
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        eprintln!("Seed: {}", seed);
+        seed
+    });
+
+    // Scene
+    let mut scene = build_scene(options.scene.as_deref(), seed);
+
+    let settings = &mut scene.settings;
+    settings.resize(options.width, options.height);
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        settings.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(max_depth) = options.max_depth {
+        settings.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = options.roulette_depth {
+        settings.roulette_depth = roulette_depth;
+    }
+    if options.diffuse_depth.is_some() {
+        settings.diffuse_depth = options.diffuse_depth;
+    }
+    if options.specular_depth.is_some() {
+        settings.specular_depth = options.specular_depth;
+    }
+    if options.transmission_depth.is_some() {
+        settings.transmission_depth = options.transmission_depth;
+    }
+    if options.adaptive_threshold.is_some() {
+        settings.adaptive_threshold = options.adaptive_threshold;
+    }
+    if let Some(min_samples) = options.min_samples_per_pixel {
+        settings.min_samples_per_pixel = min_samples;
+    }
+    settings.seed = seed;
+    if settings
+        .image_width
+        .checked_mul(settings.image_height)
+        .is_none()
+    {
+        eprintln!(
+            "Error: an image of {}x{} pixels is too large",
+            settings.image_width, settings.image_height
+        );
+        std::process::exit(2)
+    }
 
-    // Cache thread rng
-    let mut rng = rand::thread_rng();
+    let fingerprint = scene_fingerprint(options.scene.as_deref(), &scene);
+    let tile_size = options.tile_size.unwrap_or(renderer::DEFAULT_TILE_SIZE);
+    let renderer = Renderer::new(&scene, scene.camera(), scene.settings).with_tile_size(tile_size);
+
+    let mut accumulation = match checkpoint {
+        Some(checkpoint) => {
+            checkpoint
+                .check(&scene.settings, fingerprint)
+                .unwrap_or_else(|err| {
+                    eprintln!("Error resuming: {}", err);
+                    std::process::exit(1)
+                });
+            eprintln!(
+                "Resuming with {} of {} samples done",
+                checkpoint.accumulation.total_samples(),
+                checkpoint.accumulation.pixels.len() as u64
+                    * scene.settings.samples_per_pixel as u64
+            );
+            checkpoint.accumulation
+        }
+        None => renderer.accumulation(),
+    };
 
-    // World
-    let world = Arc::new(random_scene(&mut rng));
+    // Render
+    let console = ConsoleProgress {
+        encoder: encoder.as_ref(),
+        output_path: output_path.as_deref(),
+        write_interval: options.progressive.map(Duration::from_secs),
+        last_write: Mutex::new(Instant::now()),
+        samples_done: AtomicI32::new(0),
+        checkpoint_path: options.checkpoint.as_deref(),
+        checkpoint_interval: Duration::from_secs(options.checkpoint_interval.unwrap_or(60)),
+        last_checkpoint: Mutex::new(Instant::now()),
+        checkpoint_snapshot: Mutex::new(None),
+        checkpoint_saving: Mutex::new(()),
+        settings: scene.settings,
+        fingerprint,
+    };
+    let local = match &options.serve {
+        Some(address) => {
+            let listener = TcpListener::bind(address).unwrap_or_else(|err| {
+                eprintln!("Error listening on {}: {}", address, err);
+                std::process::exit(1)
+            });
+            eprintln!("Waiting for workers on {}", address);
+            let job = Job::new(&scene.settings, fingerprint);
+            let result = Coordinator::new(listener, job)
+                .with_observer(&console)
+                .with_tile_size(tile_size)
+                .render(&mut accumulation);
+            match result {
+                Ok(()) => false,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    eprintln!("\n{}, rendering the remaining tiles locally", err);
+                    true
+                }
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1)
+                }
+            }
+        }
+        None => true,
+    };
+    if local {
+        let cancel = CancelToken::new();
+        let handler_cancel = cancel.clone();
+        ctrlc::set_handler(move || {
+            // A second Ctrl-C does not wait for the tiles
+            if handler_cancel.is_cancelled() {
+                std::process::exit(130)
+            }
+            handler_cancel.cancel();
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Error handling Ctrl-C: {}", err);
+            std::process::exit(1)
+        });
+
+        let renderer = renderer.with_observer(&console).with_cancel_token(cancel);
+        if renderer.render_into(&mut accumulation, options.progressive.is_some())
+            == RenderStatus::Cancelled
+        {
+            eprintln!("\n\nCancelled, writing what was rendered");
+            if let Some(path) = console.checkpoint_path {
+                console.save_checkpoint(path, &accumulation);
+            }
+            write_image(
+                encoder.as_ref(),
+                &accumulation.average(),
+                output_path.as_deref(),
+            );
+            std::process::exit(130)
+        }
+    }
 
-    // Camera
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
+    eprintln!();
 
-    let cam: Camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        20.0,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
+    eprintln!("Writing image");
+    write_image(
+        encoder.as_ref(),
+        &accumulation.average(),
+        output_path.as_deref(),
     );
 
-    // Render
+    if let (Some(path), Some(encoder)) = (&options.sample_map, sample_map_encoder) {
+        let num_pixels = accumulation.pixels.len();
+        eprintln!(
+            "Writing sample map, {:.1} samples per pixel on average",
+            accumulation.total_samples() as f64 / num_pixels as f64
+        );
+        let map = accumulation.sample_map(scene.settings.samples_per_pixel);
+        write_image(encoder.as_ref(), &map, Some(path));
+    }
 
-    println!("P3\n{} {}\n255", IMAGE_WIDTH, IMAGE_HEIGHT);
+    eprintln!();
 
-    let n_finished = Arc::new(std::sync::atomic::AtomicI32::new(0));
+    eprintln!("Done");
+}
 
-    let pixels = (0..IMAGE_HEIGHT)
-        .into_par_iter()
-        .rev()
-        .map(move |j| {
-            let world = world.clone();
-            let n_finished = n_finished.clone();
-            (0..IMAGE_WIDTH).into_par_iter().map(move |i| {
-                let world = world.clone();
-                let n_finished = n_finished.clone();
+/// The scene file at `path`, or the random sphere scene for `seed` without one
+fn build_scene(path: Option<&Path>, seed: u64) -> Scene {
+    match path {
+        Some(path) => scene::load_scene(path).unwrap_or_else(|err| {
+            eprintln!("Error loading scene: {}", err);
+            std::process::exit(1)
+        }),
+        None => default_scene(&mut StdRng::seed_from_u64(seed)),
+    }
+}
 
-                let mut rng = rand::thread_rng();
+/// Fingerprint of `scene`, loaded from the file at `path`, for distributed renders and
+/// checkpoints. The random sphere scene is told apart by the seed alone.
+fn scene_fingerprint(path: Option<&Path>, scene: &Scene) -> u64 {
+    match path {
+        Some(path) => distributed::scene_fingerprint(path, scene).unwrap_or_else(|err| {
+            eprintln!("Error loading scene: {}", err);
+            std::process::exit(1)
+        }),
+        None => 0,
+    }
+}
 
-                let mut pixel_color = Color::default();
-                for _ in 0..SAMPLES_PER_PIXEL {
-                    let u = (i as f64 + rng.gen::<f64>()) / (IMAGE_WIDTH - 1) as f64;
-                    let v = (j as f64 + rng.gen::<f64>()) / (IMAGE_HEIGHT - 1) as f64;
-                    let ray = cam.get_ray(u, v);
-                    pixel_color += ray_color(&ray, world.as_ref(), MAX_DEPTH);
-                }
+/// Renders tiles for the coordinator at `address` until it has them all
+fn run_worker(address: &str, scene_path: Option<&Path>) {
+    let fail = |err: DistributedError| -> ! {
+        eprintln!("Error: {}", err);
+        std::process::exit(1)
+    };
+
+    eprintln!("Connecting to {}", address);
+    let worker = Worker::connect(address).unwrap_or_else(|err| fail(err));
+    let job = worker.job().clone();
+    let mut scene = build_scene(scene_path, job.seed);
+    if scene_fingerprint(scene_path, &scene) != job.scene {
+        worker.refuse("the scene differs").ok();
+        eprintln!("Error: the coordinator renders a different scene");
+        std::process::exit(1)
+    }
 
-                let n = n_finished.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    job.apply(&mut scene.settings);
+    let renderer = Renderer::new(&scene, scene.camera(), scene.settings);
+    eprintln!(
+        "Rendering {}x{} at {} spp",
+        job.image_width, job.image_height, job.samples_per_pixel
+    );
+    worker.serve(&renderer).unwrap_or_else(|err| fail(err));
+    eprintln!("Done");
+}
 
-                if n % (NUM_PIXELS / 1000) == 0 || n == NUM_PIXELS - 1 {
-                    eprint!(
-                        "\rCalculated {}/{} pixels ({:.1?}%)",
-                        n + 1,
-                        NUM_PIXELS,
-                        (n + 1) as f64 / NUM_PIXELS as f64 * 100.0,
-                    );
-                    stderr().flush().unwrap();
-                }
+/// Prints progress to stderr, rewrites the image every `write_interval` while rendering
+/// progressively and saves a checkpoint every `checkpoint_interval`. The accumulation is
+/// copied while the tiles wait for it and saved once they can go on.
+struct ConsoleProgress<'a> {
+    encoder: &'a dyn ImageEncoder,
+    output_path: Option<&'a Path>,
+    write_interval: Option<Duration>,
+    last_write: Mutex<Instant>,
+    samples_done: AtomicI32,
+    checkpoint_path: Option<&'a Path>,
+    checkpoint_interval: Duration,
+    last_checkpoint: Mutex<Instant>,
+    /// Copy of the accumulation to save next
+    checkpoint_snapshot: Mutex<Option<Accumulation>>,
+    /// Held while saving, all checkpoints go through the same temporary file
+    checkpoint_saving: Mutex<()>,
+    settings: RenderSettings,
+    /// Of the scene, see `scene_fingerprint`
+    fingerprint: u64,
+}
 
-                pixel_color
-            })
-        })
-        .flatten();
+impl ConsoleProgress<'_> {
+    fn save_checkpoint(&self, path: &Path, accumulation: &Accumulation) {
+        // A failed checkpoint leaves the previous one, the render itself can go on
+        if let Err(err) = checkpoint::save(path, &self.settings, self.fingerprint, accumulation) {
+            eprintln!("\nError saving checkpoint {}: {}", path.display(), err);
+        }
+    }
+}
 
-    let mut pixel_vec = Vec::with_capacity(NUM_PIXELS as usize);
-    pixel_vec.par_extend(pixels);
+impl RenderObserver for ConsoleProgress<'_> {
+    fn pass_started(&self, pass: usize, passes: usize, samples: i32) {
+        let samples_done = self.samples_done.fetch_add(samples, Ordering::Relaxed) + samples;
+        if passes > 1 {
+            eprintln!(
+                "{}Pass {}/{} ({} spp)",
+                if pass > 0 { "\n" } else { "" },
+                pass + 1,
+                passes,
+                samples_done
+            );
+        }
+    }
 
-    eprintln!();
+    fn tile_finished(&self, _tile: &Tile, progress: &Progress) {
+        let remaining = match progress.remaining() {
+            Some(remaining) => format!("{}s", remaining.as_secs()),
+            None => "?".to_string(),
+        };
+        eprint!(
+            "\rCalculated {}/{} tiles ({:.1}%), {} remaining, {:.2} Mrays/s   ",
+            progress.tiles_done,
+            progress.tiles,
+            progress.fraction() * 100.0,
+            remaining,
+            progress.rays_per_second() / 1.0e6
+        );
+        stderr().flush().ok();
+
+        if let Some(path) = self.checkpoint_path {
+            // Taken while saving so that an older copy never overwrites a newer one
+            let _saving = self.checkpoint_saving.lock().unwrap();
+            let snapshot = self.checkpoint_snapshot.lock().unwrap().take();
+            if let Some(snapshot) = snapshot {
+                self.save_checkpoint(path, &snapshot);
+            }
+        }
+    }
 
-    for (i, pixel_color) in pixel_vec.into_iter().enumerate() {
-        if i as i32 % (NUM_PIXELS / 1000) == 0 || i as i32 == NUM_PIXELS - 1 {
-            let n = i + 1;
-            eprint!(
-                "\rWriting pixel {}/{} ({:.1?}%)",
-                n,
-                NUM_PIXELS,
-                n as f64 / NUM_PIXELS as f64 * 100.0,
-            );
+    fn tile_merged(&self, _tile: &Tile, accumulation: &Accumulation) {
+        if self.checkpoint_path.is_none() {
+            return;
+        }
+        let mut last_checkpoint = self.last_checkpoint.lock().unwrap();
+        if last_checkpoint.elapsed() >= self.checkpoint_interval {
+            *self.checkpoint_snapshot.lock().unwrap() = Some(accumulation.clone());
+            *last_checkpoint = Instant::now();
+        }
+    }
+
+    fn worker_connected(&self, address: SocketAddr) {
+        eprintln!("\nWorker {} connected", address);
+    }
 
-            stderr().flush().unwrap();
+    fn worker_disconnected(&self, address: SocketAddr, error: Option<&DistributedError>) {
+        match error {
+            Some(err) => eprintln!("\nWorker {} lost: {}", address, err),
+            None => eprintln!("\nWorker {} finished", address),
         }
+    }
 
-        write_color(&mut handle, pixel_color, SAMPLES_PER_PIXEL).unwrap_or_else(|err| {
-            panic!(
-                "Oops, error {} saving color {} for pixel {}/{}",
-                err,
-                pixel_color,
-                i + 1,
-                NUM_PIXELS
-            )
-        })
+    fn pass_finished(&self, pass: usize, passes: usize, accumulation: &Accumulation) {
+        let write_interval = match self.write_interval {
+            Some(write_interval) if pass + 1 < passes => write_interval,
+            _ => return,
+        };
+        let mut last_write = self.last_write.lock().unwrap();
+        if last_write.elapsed() >= write_interval {
+            write_image(self.encoder, &accumulation.average(), self.output_path);
+            *last_write = Instant::now();
+        }
     }
+}
 
-    eprintln!();
+/// Finds out about an unwritable path before rendering. An image that is already there is
+/// not truncated, in case the render fails, and no empty file is left behind either.
+fn check_writable(path: &Path) -> io::Result<()> {
+    match OpenOptions::new().write(true).open(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            OpenOptions::new().write(true).create_new(true).open(path)?;
+            fs::remove_file(path)
+        }
+        result => result.map(drop),
+    }
+}
 
-    eprintln!("Done");
+/// Writes to the file at `path`, or to stdout without one, exits if that fails
+fn write_image(encoder: &dyn ImageEncoder, image: &Framebuffer, path: Option<&Path>) {
+    let result = match path {
+        Some(path) => output::write_file(encoder, image, path),
+        None => {
+            let mut handle = std::io::stdout().lock();
+            encoder
+                .encode(image, &mut handle)
+                .and_then(|()| handle.flush().map_err(Into::into))
+        }
+    };
+    if let Err(err) = result {
+        match path {
+            Some(path) => eprintln!("\nError writing {}: {}", path.display(), err),
+            None => eprintln!("\nError writing the image: {}", err),
+        }
+        std::process::exit(1)
+    }
 }
@@ -1,72 +1,239 @@
 use crate::hittable::HitRecord;
-use crate::ray::Ray;
+use crate::microfacet::{self, Ggx};
+use crate::texture::Texture;
 use crate::vec3::{Color, Vec3};
-use rand::random;
+use std::f64::consts::PI;
+use std::ops;
+use std::sync::Arc;
 
+/// Set of flags describing the kind of scattering a sampled direction came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lobe(u8);
+
+impl Lobe {
+    pub const REFLECTION: Lobe = Lobe(1);
+    pub const TRANSMISSION: Lobe = Lobe(1 << 1);
+    pub const DIFFUSE: Lobe = Lobe(1 << 2);
+    pub const GLOSSY: Lobe = Lobe(1 << 3);
+    /// A single direction, e.g. a mirror, which only sampling can find
+    pub const SPECULAR: Lobe = Lobe(1 << 4);
+
+    pub fn contains(self, other: Lobe) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for Lobe {
+    type Output = Lobe;
+
+    fn bitor(self, rhs: Lobe) -> Self::Output {
+        Lobe(self.0 | rhs.0)
+    }
+}
+
+/// Direction picked by `Material::sample`
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// BSDF times the cosine term divided by `pdf`, what the light along `wi` gets scaled by
+    pub weight: Color,
+    /// Solid angle density of `wi`, or the probability of picking it for specular lobes
+    pub pdf: f64,
+    pub lobe: Lobe,
+}
+
+/// Surface scattering, described in the local shading frame of the hit, see
+/// `HitRecord::shading_frame`. Its z axis is the shading normal, which faces the side `wo`
+/// arrives from, and all directions point away from the surface.
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    /// BSDF times the cosine term for light arriving from `wi` and leaving towards `wo`.
+    /// Black for specular lobes, which no given direction can hit.
+    fn eval(&self, _rec: &HitRecord, _wo: Vec3, _wi: Vec3) -> Color {
+        Color::default()
+    }
+
+    /// Picks the direction light arrives from given uniform numbers `u` in [0, 1), or
+    /// `None` if the path ends here
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample>;
+
+    /// Solid angle density with which `sample` picks `wi`, zero for specular lobes
+    fn pdf(&self, _rec: &HitRecord, _wo: Vec3, _wi: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Whether every lobe at `rec` is specular, so `eval` and `pdf` are zero for any `wi`
+    /// and sampling lights directly cannot find anything. Materials mixing specular lobes
+    /// with others are not, whichever lobe `sample` happens to pick.
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        false
+    }
+
+    /// Radiance given off by the surface itself, black for everything but lights
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+// Lets several objects share one material
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        self.as_ref().eval(rec, wo, wi)
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        self.as_ref().sample(rec, wo, u)
+    }
+
+    fn pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        self.as_ref().pdf(rec, wo, wi)
+    }
+
+    fn is_specular(&self, rec: &HitRecord, wo: Vec3) -> bool {
+        self.as_ref().is_specular(rec, wo)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.as_ref().emitted(rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.as_ref().is_emissive()
+    }
+}
+
+/// Mirror direction of `wo` about the shading normal
+fn mirror(wo: Vec3) -> Vec3 {
+    Vec3::new(-wo.x, -wo.y, wo.z)
+}
+
+#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
-    pub fn new(albedo: Color) -> Lambertian {
-        Lambertian { albedo }
+    /// Takes a plain `Color` or any other texture
+    pub fn new(albedo: impl Texture + 'static) -> Lambertian {
+        Lambertian {
+            albedo: Arc::new(albedo),
+        }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>
-    where
-        Self: Sized,
-    {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector(&mut rand::thread_rng());
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(rec, wo, wi)
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+    fn sample(&self, rec: &HitRecord, _wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        // Cosine distribution, a uniform point on the disk projected up to the hemisphere
+        let r = u[1].sqrt();
+        let phi = 2.0 * PI * u[2];
+        let wi = Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u[1]).sqrt());
+        if wi.z <= 0.0 {
+            return None;
         }
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
 
-        Some((attenuation, scattered))
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: wi.z / PI,
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+        })
+    }
+
+    fn pdf(&self, _rec: &HitRecord, _wo: Vec3, wi: Vec3) -> f64 {
+        wi.z.max(0.0) / PI
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Mirror blurred by offsetting the reflection with a random point of a ball of radius
+/// `fuzz`. Directions ending up below the surface are absorbed.
+#[derive(Clone)]
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
-    pub fn new(albedo: Color, f: f64) -> Metal {
+    /// Takes a plain `Color` or any other texture
+    pub fn new(albedo: impl Texture + 'static, f: f64) -> Metal {
         Metal {
-            albedo,
-            fuzz: f.clamp(-f64::INFINITY, 1.0),
+            albedo: Arc::new(albedo),
+            fuzz: f.min(1.0),
         }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>
-    where
-        Self: Sized,
-    {
-        let reflected = ray_in.direction.normalize().reflect(rec.normal);
-
-        let scattered = Ray::new(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(&mut rand::thread_rng()),
-        );
-        let attenuation = self.albedo;
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(rec, wo, wi)
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let albedo = self.albedo.value(rec.u, rec.v, rec.p);
+        if self.fuzz == 0.0 {
+            return Some(BsdfSample {
+                wi: mirror(wo),
+                weight: albedo,
+                pdf: 1.0,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            });
+        }
+
+        // Uniform point in the ball, the cube root spreads the radii by volume
+        let radius = self.fuzz * u[0].cbrt();
+        let z = 1.0 - 2.0 * u[1];
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[2];
+        let offset = radius * Vec3::new(r * phi.cos(), r * phi.sin(), z);
+
+        let wi = (mirror(wo) + offset).normalize();
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            wi,
+            weight: albedo,
+            pdf: self.pdf(rec, wo, wi),
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+
+    fn pdf(&self, _rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        if self.fuzz == 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
 
-        Some((attenuation, scattered))
+        // The directions of the points of a uniform ball around the unit length mirror
+        // direction: the density is the ball's volume along the ray through `wi`, weighted
+        // by the squared distance, divided by its total volume
+        let wi = wi.normalize();
+        let b = wi.dot(mirror(wo));
+        let discriminant = b * b - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t_far = b + discriminant.sqrt();
+        if t_far <= 0.0 {
+            return 0.0;
+        }
+        let t_near = (b - discriminant.sqrt()).max(0.0);
+
+        (t_far.powi(3) - t_near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
+
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        self.fuzz == 0.0
     }
 }
 
+/// Smooth glass that either reflects or refracts, picked by Schlick's approximation
 #[derive(Debug, Clone, Copy)]
 pub struct Dielectric {
     ir: f64,
@@ -88,32 +255,894 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>
-    where
-        Self: Sized,
-    {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio;
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let refraction_ratio = if rec.front_face {
+            1.0 / self.ir
+        } else {
+            self.ir
+        };
+
+        let cos_theta = wo.z.min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflectance = if refraction_ratio * sin_theta > 1.0 {
+            1.0
+        } else {
+            Dielectric::reflectance(cos_theta, refraction_ratio)
+        };
+
+        // Picking the lobe by the reflectance cancels it from the weight
+        let (wi, pdf, lobe) = if u[0] < reflectance {
+            (mirror(wo), reflectance, Lobe::REFLECTION)
+        } else {
+            let normal = Vec3::new(0.0, 0.0, 1.0);
+            let wi = microfacet::refract(wo, normal, 1.0 / refraction_ratio)?;
+            (wi, 1.0 - reflectance, Lobe::TRANSMISSION)
+        };
+
+        Some(BsdfSample {
+            wi,
+            weight: Color::new(1.0, 1.0, 1.0),
+            pdf,
+            lobe: Lobe::SPECULAR | lobe,
+        })
+    }
+
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        true
+    }
+}
+
+/// Metal with a GGX microfacet surface and the Fresnel reflectance of its complex index of
+/// refraction `eta + i k`, given per color channel
+#[derive(Debug, Clone, Copy)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn eval(&self, _rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::default();
+        }
+        let h = (wo + wi).normalize();
+        let fresnel = microfacet::fresnel_conductor(wo.dot(h), self.eta, self.k);
+        self.distribution.reflection(wo, wi) * fresnel
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            return Some(BsdfSample {
+                wi: mirror(wo),
+                weight: microfacet::fresnel_conductor(wo.z, self.eta, self.k),
+                pdf: 1.0,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            });
+        }
+
+        let h = self.distribution.sample_visible_normal(wo, u[1], u[2]);
+        let wi = microfacet::reflect(wo, h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        // Sampling visible normals leaves only the masking of the outgoing direction
+        let masking = self.distribution.g2(wo, wi) / self.distribution.g1(wo);
+        let fresnel = microfacet::fresnel_conductor(wo.dot(h), self.eta, self.k);
+
+        Some(BsdfSample {
+            wi,
+            weight: masking * fresnel,
+            pdf: self.pdf(rec, wo, wi),
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+
+    fn pdf(&self, _rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        self.distribution.reflection_pdf(wo, wi)
+    }
+
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+/// Indices of refraction closer to 1 than this bend light too little to sample it as a rough
+/// refraction
+const MIN_INDEX_CONTRAST: f64 = 1.0e-3;
+
+/// Glass with a GGX microfacet surface, both reflecting and refracting
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectric {
+    ir: f64,
+    distribution: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(index_of_refraction: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            ir: index_of_refraction,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+
+    /// Whether the surface acts as smooth glass. Light passes straight through a surface
+    /// between matching indices however rough it is.
+    fn is_smooth(&self) -> bool {
+        self.distribution.is_smooth() || (self.ir - 1.0).abs() < MIN_INDEX_CONTRAST
+    }
+
+    /// Index of the side the normal points away from, relative to the side it points into
+    fn eta(&self, rec: &HitRecord) -> f64 {
         if rec.front_face {
-            refraction_ratio = 1.0 / self.ir;
+            self.ir
         } else {
-            refraction_ratio = self.ir;
+            1.0 / self.ir
         }
+    }
 
-        let unit_direction = ray_in.direction.normalize();
-        let cos_theta = (-unit_direction).dot(rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    /// Density of sampling `wi` with a rough surface, both lobes included
+    fn rough_pdf(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalize();
+            let fresnel = microfacet::fresnel_dielectric(wo.dot(h), eta);
+            return fresnel * self.distribution.reflection_pdf(wo, wi);
+        }
+
+        // Generalized half vector of refraction, turned to the outer side
+        let mut h = -(wo + eta * wi).normalize();
+        if h.z < 0.0 {
+            h = -h;
+        }
+        let (cos_o, cos_i) = (wo.dot(h), wi.dot(h));
+        if cos_o <= 0.0 || cos_i >= 0.0 {
+            return 0.0;
+        }
+
+        let fresnel = microfacet::fresnel_dielectric(cos_o, eta);
+        let denominator = cos_o + eta * cos_i;
+        let jacobian = eta * eta * cos_i.abs() / (denominator * denominator);
+        (1.0 - fresnel) * self.distribution.visible_normal_pdf(wo, h) * jacobian
+    }
+}
+
+impl Material for RoughDielectric {
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        if self.is_smooth() {
+            return Color::default();
+        }
+        // Same weight as the sampled directions get, like `Dielectric` radiance is not
+        // rescaled by the squared index ratio
+        let pdf = self.rough_pdf(wo, wi, self.eta(rec));
+        let weight = self.distribution.g2(wo, wi) / self.distribution.g1(wo);
+        Color::new(1.0, 1.0, 1.0) * (pdf * weight)
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction;
+        let eta = self.eta(rec);
+        let smooth = self.is_smooth();
+        let h = if smooth {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible_normal(wo, u[1], u[2])
+        };
 
-        if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > random() {
-            direction = unit_direction.reflect(rec.normal);
+        // Picking the lobe by the Fresnel term cancels it from the weight
+        let fresnel = microfacet::fresnel_dielectric(wo.dot(h), eta);
+        let (wi, choice, lobe) = if u[0] < fresnel {
+            let wi = microfacet::reflect(wo, h);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            (wi, fresnel, Lobe::REFLECTION)
         } else {
-            direction = unit_direction.refract(rec.normal, refraction_ratio);
+            let wi = microfacet::refract(wo, h, eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            (wi, 1.0 - fresnel, Lobe::TRANSMISSION)
+        };
+
+        if smooth {
+            return Some(BsdfSample {
+                wi,
+                weight: Color::new(1.0, 1.0, 1.0),
+                pdf: choice,
+                lobe: Lobe::SPECULAR | lobe,
+            });
         }
 
-        let scattered = Ray::new(rec.p, direction);
-        Some((attenuation, scattered))
+        let masking = self.distribution.g2(wo, wi) / self.distribution.g1(wo);
+        Some(BsdfSample {
+            wi,
+            weight: Color::new(masking, masking, masking),
+            pdf: self.rough_pdf(wo, wi, eta),
+            lobe: Lobe::GLOSSY | lobe,
+        })
+    }
+
+    fn pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        if self.is_smooth() {
+            return 0.0;
+        }
+        self.rough_pdf(wo, wi, self.eta(rec))
+    }
+
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        self.is_smooth()
+    }
+}
+
+/// Roughness of the clear coat of `Principled`, a thin glossy varnish
+const CLEARCOAT_ROUGHNESS: f64 = 0.25;
+
+/// Reflectance of the clear coat at normal incidence, that of an index of 1.5
+const CLEARCOAT_F0: f64 = 0.04;
+
+/// Schlick's approximation of the Fresnel reflectance from the one at normal incidence
+fn schlick(f0: f64, cosine: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cosine.clamp(0.0, 1.0)).powi(5)
+}
+
+/// "Uber" material after Disney's principled BSDF: a diffuse base with sheen under a
+/// dielectric specular layer, blended into metal by `metallic` and into glass by
+/// `transmission`, with an optional clear coat on top. Every parameter is a texture, the
+/// scalar ones read the mean of its channels.
+///
+/// The lobes are blended with weights that only depend on the outgoing direction and sum to
+/// one, and none of them reflects more light than it receives, so the blend never does
+/// either.
+#[derive(Clone)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+}
+
+impl Principled {
+    /// Rough plastic of the given base color, the `with_*` methods set the other parameters
+    pub fn new(base_color: impl Texture + 'static) -> Principled {
+        let constant = |value| -> Arc<dyn Texture> { Arc::new(Color::new(value, value, value)) };
+        Principled {
+            base_color: Arc::new(base_color),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            clearcoat: constant(0.0),
+            sheen: constant(0.0),
+            transmission: constant(0.0),
+        }
+    }
+
+    /// Blend from dielectric (0) to metal (1), which tints its reflections by the base color
+    pub fn with_metallic(mut self, metallic: impl Texture + 'static) -> Self {
+        self.metallic = Arc::new(metallic);
+        self
+    }
+
+    /// Perceptual roughness of the specular, metal and glass lobes
+    pub fn with_roughness(mut self, roughness: impl Texture + 'static) -> Self {
+        self.roughness = Arc::new(roughness);
+        self
+    }
+
+    /// Dielectric reflectance at normal incidence, 0.08 times this. The default of 0.5
+    /// matches an index of refraction of 1.5, which glass uses too.
+    pub fn with_specular(mut self, specular: impl Texture + 'static) -> Self {
+        self.specular = Arc::new(specular);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: impl Texture + 'static) -> Self {
+        self.clearcoat = Arc::new(clearcoat);
+        self
+    }
+
+    /// Brightens the diffuse base towards white at grazing angles, like cloth
+    pub fn with_sheen(mut self, sheen: impl Texture + 'static) -> Self {
+        self.sheen = Arc::new(sheen);
+        self
+    }
+
+    /// Blend from opaque (0) to glass (1), which tints refracted light by the base color
+    pub fn with_transmission(mut self, transmission: impl Texture + 'static) -> Self {
+        self.transmission = Arc::new(transmission);
+        self
+    }
+
+    fn lobes(&self, rec: &HitRecord, wo: Vec3) -> PrincipledLobes {
+        let scalar = |texture: &Arc<dyn Texture>| {
+            let value = texture.value(rec.u, rec.v, rec.p);
+            ((value.x + value.y + value.z) / 3.0).clamp(0.0, 1.0)
+        };
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let base_color = Color::new(
+            base_color.x.clamp(0.0, 1.0),
+            base_color.y.clamp(0.0, 1.0),
+            base_color.z.clamp(0.0, 1.0),
+        );
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let specular = scalar(&self.specular);
+        let clearcoat = scalar(&self.clearcoat);
+        let transmission = scalar(&self.transmission);
+
+        let f0 = 0.08 * specular;
+        let ir = (1.0 + f0.sqrt()) / (1.0 - f0.sqrt());
+
+        let mut weights = [0.0; PrincipledLobes::COUNT];
+        if !rec.front_face && transmission > 0.0 {
+            // Inside a transmissive object the glass is the only way out
+            weights[PrincipledLobes::GLASS] = 1.0;
+        } else {
+            let coat = clearcoat * schlick(CLEARCOAT_F0, wo.z);
+            let dielectric = (1.0 - coat) * (1.0 - metallic);
+            let opaque = dielectric * (1.0 - transmission);
+            let specular = opaque * schlick(f0, wo.z);
+            weights[PrincipledLobes::COAT] = coat;
+            weights[PrincipledLobes::METAL] = (1.0 - coat) * metallic;
+            weights[PrincipledLobes::SPECULAR] = specular;
+            weights[PrincipledLobes::DIFFUSE] = opaque - specular;
+            weights[PrincipledLobes::GLASS] = dielectric * transmission;
+        }
+
+        PrincipledLobes {
+            base_color,
+            sheen: scalar(&self.sheen),
+            distribution: Ggx::from_roughness(roughness),
+            coat: Ggx::from_roughness(CLEARCOAT_ROUGHNESS),
+            glass: RoughDielectric::new(ir, roughness),
+            weights,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        let lobes = self.lobes(rec, wo);
+        (0..PrincipledLobes::COUNT).fold(Color::default(), |sum, lobe| {
+            sum + lobes.weights[lobe] * lobes.eval(lobe, rec, wo, wi)
+        })
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let lobes = self.lobes(rec, wo);
+
+        // Pick a lobe by its weight and stretch the part of u[0] that picked it back over
+        // [0, 1), the lobe may need it for a choice of its own
+        let mut chosen = None;
+        let mut u0 = u[0];
+        for (lobe, &weight) in lobes.weights.iter().enumerate() {
+            if weight <= 0.0 {
+                continue;
+            }
+            chosen = Some(lobe);
+            if u0 < weight {
+                u0 /= weight;
+                break;
+            }
+            u0 -= weight;
+        }
+        let chosen = chosen?;
+
+        let sample = lobes.sample(chosen, rec, wo, [u0.min(1.0), u[1], u[2]])?;
+        if sample.lobe.contains(Lobe::SPECULAR) {
+            return Some(BsdfSample {
+                pdf: lobes.weights[chosen] * sample.pdf,
+                ..sample
+            });
+        }
+
+        // Any of the other lobes could have picked the same direction
+        let pdf = self.pdf(rec, wo, sample.wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: self.eval(rec, wo, sample.wi) / pdf,
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        let lobes = self.lobes(rec, wo);
+        (0..PrincipledLobes::COUNT)
+            .map(|lobe| lobes.weights[lobe] * lobes.pdf(lobe, rec, wo, wi))
+            .sum()
+    }
+
+    fn is_specular(&self, rec: &HitRecord, wo: Vec3) -> bool {
+        let lobes = self.lobes(rec, wo);
+        (0..PrincipledLobes::COUNT)
+            .all(|lobe| lobes.weights[lobe] <= 0.0 || lobes.is_specular(lobe))
+    }
+}
+
+/// The lobes of `Principled` at one point
+struct PrincipledLobes {
+    base_color: Color,
+    sheen: f64,
+    distribution: Ggx,
+    coat: Ggx,
+    glass: RoughDielectric,
+    /// Indexed by the lobe constants below
+    weights: [f64; PrincipledLobes::COUNT],
+}
+
+impl PrincipledLobes {
+    const COAT: usize = 0;
+    const METAL: usize = 1;
+    const SPECULAR: usize = 2;
+    const DIFFUSE: usize = 3;
+    const GLASS: usize = 4;
+    const COUNT: usize = 5;
+
+    fn is_specular(&self, lobe: usize) -> bool {
+        match lobe {
+            PrincipledLobes::DIFFUSE => false,
+            PrincipledLobes::GLASS => self.glass.is_smooth(),
+            _ => self.reflection_distribution(lobe).is_smooth(),
+        }
+    }
+
+    fn reflection_distribution(&self, lobe: usize) -> &Ggx {
+        if lobe == PrincipledLobes::COAT {
+            &self.coat
+        } else {
+            &self.distribution
+        }
+    }
+
+    /// Fresnel term of the reflection lobes, the dielectric ones have theirs in the weights
+    fn fresnel(&self, lobe: usize, cos_i: f64) -> Color {
+        if lobe == PrincipledLobes::METAL {
+            let white = Color::new(1.0, 1.0, 1.0);
+            self.base_color + (white - self.base_color) * (1.0 - cos_i.clamp(0.0, 1.0)).powi(5)
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    /// Refracted light takes on the base color
+    fn glass_tint(&self, wi: Vec3) -> Color {
+        if wi.z < 0.0 {
+            self.base_color
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    fn eval(&self, lobe: usize, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        match lobe {
+            PrincipledLobes::DIFFUSE => {
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return Color::default();
+                }
+                let h = (wo + wi).normalize();
+                let sheen = self.sheen * (1.0 - wi.dot(h)).powi(5);
+                let white = Color::new(1.0, 1.0, 1.0);
+                ((1.0 - sheen) * self.base_color + sheen * white) * (wi.z / PI)
+            }
+            PrincipledLobes::GLASS => self.glass.eval(rec, wo, wi) * self.glass_tint(wi),
+            _ => {
+                let distribution = self.reflection_distribution(lobe);
+                if distribution.is_smooth() {
+                    return Color::default();
+                }
+                let h = (wo + wi).normalize();
+                distribution.reflection(wo, wi) * self.fresnel(lobe, wo.dot(h))
+            }
+        }
+    }
+
+    fn sample(&self, lobe: usize, rec: &HitRecord, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        match lobe {
+            PrincipledLobes::DIFFUSE => {
+                let r = u[1].sqrt();
+                let phi = 2.0 * PI * u[2];
+                let wi = Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u[1]).sqrt());
+                let pdf = self.pdf(lobe, rec, wo, wi);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample {
+                    wi,
+                    weight: self.eval(lobe, rec, wo, wi) / pdf,
+                    pdf,
+                    lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+                })
+            }
+            PrincipledLobes::GLASS => {
+                let sample = self.glass.sample(rec, wo, u)?;
+                Some(BsdfSample {
+                    weight: sample.weight * self.glass_tint(sample.wi),
+                    ..sample
+                })
+            }
+            _ => {
+                let distribution = self.reflection_distribution(lobe);
+                if distribution.is_smooth() {
+                    return Some(BsdfSample {
+                        wi: mirror(wo),
+                        weight: self.fresnel(lobe, wo.z),
+                        pdf: 1.0,
+                        lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+                    });
+                }
+
+                let h = distribution.sample_visible_normal(wo, u[1], u[2]);
+                let wi = microfacet::reflect(wo, h);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                let masking = distribution.g2(wo, wi) / distribution.g1(wo);
+                Some(BsdfSample {
+                    wi,
+                    weight: masking * self.fresnel(lobe, wo.dot(h)),
+                    pdf: distribution.reflection_pdf(wo, wi),
+                    lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+                })
+            }
+        }
+    }
+
+    fn pdf(&self, lobe: usize, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        match lobe {
+            PrincipledLobes::DIFFUSE => wi.z.max(0.0) / PI,
+            PrincipledLobes::GLASS => self.glass.pdf(rec, wo, wi),
+            _ => {
+                let distribution = self.reflection_distribution(lobe);
+                if distribution.is_smooth() {
+                    return 0.0;
+                }
+                distribution.reflection_pdf(wo, wi)
+            }
+        }
+    }
+}
+
+/// Phase function of a medium that scatters equally in all directions
+#[derive(Clone)]
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    /// Takes a plain `Color` or any other texture
+    pub fn new(albedo: impl Texture + 'static) -> Isotropic {
+        Isotropic {
+            albedo: Arc::new(albedo),
+        }
+    }
+}
+
+impl Material for Isotropic {
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(rec, wo, wi)
+    }
+
+    fn sample(&self, rec: &HitRecord, _wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        let z = 1.0 - 2.0 * u[1];
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[2];
+        let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        let side = if wi.z >= 0.0 {
+            Lobe::REFLECTION
+        } else {
+            Lobe::TRANSMISSION
+        };
+
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: 1.0 / (4.0 * PI),
+            lobe: Lobe::DIFFUSE | side,
+        })
+    }
+
+    fn pdf(&self, _rec: &HitRecord, _wo: Vec3, _wi: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(color: Color, intensity: f64) -> DiffuseLight {
+        DiffuseLight {
+            emit: intensity * color,
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _rec: &HitRecord, _wo: Vec3, _u: [f64; 3]) -> Option<BsdfSample> {
+        None
+    }
+
+    // Lights do not scatter at all
+    fn is_specular(&self, _rec: &HitRecord, _wo: Vec3) -> bool {
+        true
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        // Only the front side glows so lights facing a wall don't light up its back
+        if rec.front_face {
+            self.emit
+        } else {
+            Color::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chi_square::assert_samples_match;
+    use crate::vec3::Point3;
+    use rand::Rng;
+    use rayon::prelude::*;
+
+    fn hit(front_face: bool) -> HitRecord<'static> {
+        let mut rec = HitRecord::new(Point3::default(), 1.0, None);
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = front_face;
+        rec
+    }
+
+    fn assert_close(name: &str, what: &str, actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= 1.0e-6 * expected.abs().max(1.0),
+            "{}: {} is {}, expected {}",
+            name,
+            what,
+            actual,
+            expected
+        );
+    }
+
+    /// Checks that `sample` picks directions by `pdf`, reports the density and weight `pdf`
+    /// and `eval` give them, and reflects no more light than arrives
+    fn check_sampling(
+        name: &str,
+        material: &dyn Material,
+        rec: &HitRecord,
+        wo: Vec3,
+        count: usize,
+    ) {
+        let wo = wo.normalize();
+        let mut sum = Color::default();
+        let mut squares = Color::default();
+        assert_samples_match(
+            name,
+            count,
+            |rng| {
+                let sample = material.sample(rec, wo, [rng.gen(), rng.gen(), rng.gen()])?;
+                sum += sample.weight;
+                squares += sample.weight * sample.weight;
+                if sample.lobe.contains(Lobe::SPECULAR) {
+                    return None;
+                }
+                let pdf = material.pdf(rec, wo, sample.wi);
+                assert_close(name, "sampled density", sample.pdf, pdf);
+                let weight = material.eval(rec, wo, sample.wi) / pdf;
+                assert_close(name, "red weight", sample.weight.x, weight.x);
+                assert_close(name, "green weight", sample.weight.y, weight.y);
+                assert_close(name, "blue weight", sample.weight.z, weight.z);
+                Some(sample.wi)
+            },
+            |wi| material.pdf(rec, wo, wi),
+        );
+
+        // Absorbed samples count as black
+        let n = count as f64;
+        let albedo = sum / n;
+        for &(mean, squares) in &[
+            (albedo.x, squares.x),
+            (albedo.y, squares.y),
+            (albedo.z, squares.z),
+        ] {
+            let error = ((squares / n - mean * mean).max(0.0) / n).sqrt();
+            assert!(
+                mean <= 1.0 + 4.0 * error + 1.0e-9,
+                "{}: albedo {} with standard error {}",
+                name,
+                mean,
+                error
+            );
+        }
+    }
+
+    const VIEWS: [(f64, f64, f64); 3] = [(0.0, 0.0, 1.0), (0.5, 0.2, 0.8), (0.9, -0.3, 0.15)];
+
+    #[test]
+    fn lambertian_sampling() {
+        let material = Lambertian::new(Color::new(0.5, 0.6, 0.7));
+        for &(x, y, z) in &VIEWS {
+            check_sampling(
+                "lambertian",
+                &material,
+                &hit(true),
+                Vec3::new(x, y, z),
+                50_000,
+            );
+        }
+    }
+
+    #[test]
+    fn metal_sampling() {
+        for &fuzz in &[0.2, 0.7, 1.0] {
+            let material = Metal::new(Color::new(0.9, 0.8, 0.7), fuzz);
+            for &(x, y, z) in &VIEWS {
+                check_sampling("metal", &material, &hit(true), Vec3::new(x, y, z), 50_000);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_sampling() {
+        let (eta, k) = (Color::new(0.27, 0.68, 1.22), Color::new(3.61, 2.62, 2.29));
+        for &roughness in &[0.3, 0.6, 1.0] {
+            let material = Conductor::new(eta, k, roughness);
+            for &(x, y, z) in &VIEWS {
+                check_sampling(
+                    "conductor",
+                    &material,
+                    &hit(true),
+                    Vec3::new(x, y, z),
+                    50_000,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sampling() {
+        for &roughness in &[0.3, 0.7] {
+            let material = RoughDielectric::new(1.5, roughness);
+            for &front_face in &[true, false] {
+                for &(x, y, z) in &VIEWS {
+                    let name = format!("rough dielectric, front face {}", front_face);
+                    check_sampling(
+                        &name,
+                        &material,
+                        &hit(front_face),
+                        Vec3::new(x, y, z),
+                        50_000,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn isotropic_sampling() {
+        let material = Isotropic::new(Color::new(0.8, 0.8, 0.8));
+        check_sampling(
+            "isotropic",
+            &material,
+            &hit(true),
+            Vec3::new(0.3, 0.1, 0.9),
+            50_000,
+        );
+    }
+
+    #[test]
+    fn mixed_principled_sampling() {
+        // Smooth, so the sampled directions mix delta lobes with the diffuse base
+        let material = Principled::new(Color::new(0.8, 0.3, 0.2))
+            .with_roughness(Color::new(0.0, 0.0, 0.0))
+            .with_metallic(Color::new(0.5, 0.5, 0.5));
+        for &(x, y, z) in &VIEWS {
+            check_sampling(
+                "smooth principled",
+                &material,
+                &hit(true),
+                Vec3::new(x, y, z),
+                50_000,
+            );
+        }
+    }
+
+    #[test]
+    fn principled_parameter_grid() {
+        // Every combination of the edges 0 and 1 of each parameter, each seen from one of
+        // the views and sides, over a white base that reflects the most. The samples come
+        // from the fixed seed of `assert_samples_match`, so the outcome never changes.
+        let gray = |value: f64| Color::new(value, value, value);
+        (0..1_usize << 6).into_par_iter().for_each(|index| {
+            let level = |parameter: u32| ((index >> parameter) & 1) as f64;
+            let material = Principled::new(gray(1.0))
+                .with_metallic(gray(level(0)))
+                .with_roughness(gray(level(1)))
+                .with_specular(gray(level(2)))
+                .with_clearcoat(gray(level(3)))
+                .with_sheen(gray(level(4)))
+                .with_transmission(gray(level(5)));
+            let (x, y, z) = VIEWS[index % VIEWS.len()];
+            // By parity, so that each side sees both edges of every parameter
+            let front_face = index.count_ones() % 2 == 0;
+            let name = format!(
+                "principled metallic={} roughness={} specular={} clearcoat={} sheen={} \
+                 transmission={}, front face {}",
+                level(0),
+                level(1),
+                level(2),
+                level(3),
+                level(4),
+                level(5),
+                front_face
+            );
+            check_sampling(
+                &name,
+                &material,
+                &hit(front_face),
+                Vec3::new(x, y, z),
+                2_000,
+            );
+        });
+    }
+
+    #[test]
+    fn specular_materials() {
+        let rec = hit(true);
+        let wo = Vec3::new(0.5, 0.2, 0.8).normalize();
+        let smooth = Color::new(0.0, 0.0, 0.0);
+        let full = Color::new(1.0, 1.0, 1.0);
+
+        assert!(Dielectric::new(1.5).is_specular(&rec, wo));
+        assert!(Metal::new(full, 0.0).is_specular(&rec, wo));
+        assert!(!Metal::new(full, 0.1).is_specular(&rec, wo));
+        assert!(RoughDielectric::new(1.5, 0.0).is_specular(&rec, wo));
+        assert!(!Lambertian::new(full).is_specular(&rec, wo));
+
+        let chrome = Principled::new(full)
+            .with_metallic(full)
+            .with_roughness(smooth);
+        assert!(chrome.is_specular(&rec, wo));
+        // A smooth coat over a diffuse base still has the base to light directly
+        let plastic = Principled::new(full).with_roughness(smooth);
+        assert!(!plastic.is_specular(&rec, wo));
+        assert!(!plastic.clone().with_clearcoat(full).is_specular(&rec, wo));
     }
 }
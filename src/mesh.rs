@@ -0,0 +1,288 @@
+use crate::aabb::Aabb;
+use crate::bvh::{BvhNode, SplitMethod};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::triangle::{self, Uv};
+use crate::vec3::{Point3, Vec3};
+use rand::Rng;
+use std::sync::Arc;
+
+/// Indices of one triangle corner into the vertex buffers of its mesh
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshVertex {
+    pub position: usize,
+    pub normal: Option<usize>,
+    pub uv: Option<usize>,
+}
+
+/// Indexed triangle mesh, vertex attributes are stored once and shared between faces
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Uv>,
+    faces: Vec<[MeshVertex; 3]>,
+    material: Box<dyn Material>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Uv>,
+        faces: Vec<[MeshVertex; 3]>,
+        material: impl Material + 'static,
+    ) -> Self {
+        for vertex in faces.iter().flatten() {
+            assert!(
+                vertex.position < positions.len()
+                    && vertex.normal.is_none_or(|n| n < normals.len())
+                    && vertex.uv.is_none_or(|uv| uv < uvs.len()),
+                "Mesh vertex {:?} indexes past the end of the vertex buffers",
+                vertex
+            );
+        }
+
+        TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            faces,
+            material: Box::new(material),
+        }
+    }
+
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> impl Iterator<Item = MeshTriangle> + '_ {
+        (0..mesh.faces.len()).map(move |face| MeshTriangle {
+            mesh: mesh.clone(),
+            face,
+        })
+    }
+
+    /// Builds an acceleration structure over the faces without boxing each of them
+    pub fn into_bvh(self, split_method: SplitMethod) -> BvhNode<MeshTriangle> {
+        let mesh = Arc::new(self);
+        BvhNode::from_primitives(TriangleMesh::triangles(&mesh).collect(), split_method)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// The faces as one light, or `None` if they have no area to sample
+    pub fn light(&self, split_method: SplitMethod) -> Option<MeshLight> {
+        let faces: Vec<LightFace> = (0..self.faces.len())
+            .map(|face| LightFace(self.face_positions(face)))
+            .collect();
+        let mut total = 0.0;
+        let cumulative_areas: Vec<f64> = faces
+            .iter()
+            .map(|face| {
+                total += triangle::area(&face.0);
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return None;
+        }
+
+        Some(MeshLight {
+            vertices: faces.iter().map(|face| face.0).collect(),
+            cumulative_areas,
+            faces: BvhNode::from_primitives(faces, split_method),
+        })
+    }
+
+    fn face_positions(&self, face: usize) -> [Point3; 3] {
+        let [a, b, c] = self.faces[face];
+        [
+            self.positions[a.position],
+            self.positions[b.position],
+            self.positions[c.position],
+        ]
+    }
+}
+
+/// A single face of a shared `TriangleMesh`
+pub struct MeshTriangle {
+    mesh: Arc<TriangleMesh>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mesh = self.mesh.as_ref();
+        let vertices = mesh.face_positions(self.face);
+        let (t, b1, b2) = triangle::intersect(ray, &vertices, t_min, t_max)?;
+
+        // Attributes are only interpolated when every corner has them
+        let [a, b, c] = mesh.faces[self.face];
+        let normals = match (a.normal, b.normal, c.normal) {
+            (Some(na), Some(nb), Some(nc)) => {
+                Some([mesh.normals[na], mesh.normals[nb], mesh.normals[nc]])
+            }
+            _ => None,
+        };
+        let uvs = match (a.uv, b.uv, c.uv) {
+            (Some(ta), Some(tb), Some(tc)) => Some([mesh.uvs[ta], mesh.uvs[tb], mesh.uvs[tc]]),
+            _ => None,
+        };
+
+        let mut rec = HitRecord::new(ray.at(t), t, Some(mesh.material.as_ref()));
+        triangle::set_surface(
+            &mut rec,
+            ray,
+            &vertices,
+            normals.as_ref(),
+            uvs.as_ref(),
+            (b1, b2),
+        );
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle::bounding_box(&self.mesh.face_positions(self.face)))
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        triangle::pdf_value(&self.mesh.face_positions(self.face), origin, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        triangle::random_point(&self.mesh.face_positions(self.face), sampler) - origin
+    }
+}
+
+/// The faces of an emissive mesh sampled as a single light, uniformly by area. It only
+/// stands in the list of lights, the mesh itself is in the world.
+pub struct MeshLight {
+    faces: BvhNode<LightFace>,
+    vertices: Vec<[Point3; 3]>,
+    /// Area of the faces up to and including each one, to pick them in proportion to it
+    cumulative_areas: Vec<f64>,
+}
+
+impl MeshLight {
+    fn area(&self) -> f64 {
+        *self.cumulative_areas.last().unwrap()
+    }
+}
+
+impl Hittable for MeshLight {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.faces.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.faces.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        // Every face the ray crosses could have been the one sampled, not only the nearest
+        let ray = Ray::new(origin, direction, 0.0);
+        let mut pdf = 0.0;
+        let mut t_min = 0.001;
+        while let Some(rec) = self.faces.hit(&ray, t_min, f64::INFINITY) {
+            pdf += triangle::area_density_to_solid_angle(rec.normal, rec.t, direction, self.area());
+            t_min = rec.t.next_up();
+        }
+
+        pdf
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        let target = sampler.gen::<f64>() * self.area();
+        let face = self
+            .cumulative_areas
+            .partition_point(|&area| area <= target)
+            .min(self.vertices.len() - 1);
+        triangle::random_point(&self.vertices[face], sampler) - origin
+    }
+}
+
+/// A face of a `MeshLight`, its hits carry the geometric normal and no material
+struct LightFace([Point3; 3]);
+
+impl Hittable for LightFace {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, _, _) = triangle::intersect(ray, &self.0, t_min, t_max)?;
+        let mut rec = HitRecord::new(ray.at(t), t, None);
+        rec.set_face_normal(ray, triangle::normal(&self.0).normalize());
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle::bounding_box(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chi_square::assert_samples_match;
+    use crate::material::DiffuseLight;
+    use crate::vec3::Color;
+
+    fn vertex(position: usize) -> MeshVertex {
+        MeshVertex {
+            position,
+            normal: None,
+            uv: None,
+        }
+    }
+
+    #[test]
+    fn lights_sample_faces_by_area() {
+        // A small face in front of a large one it partly hides, and one off to the side
+        let positions = vec![
+            Point3::new(-0.5, -0.5, -1.0),
+            Point3::new(0.5, -0.5, -1.0),
+            Point3::new(0.0, 0.5, -1.0),
+            Point3::new(-2.0, -2.0, -3.0),
+            Point3::new(2.0, -2.0, -3.0),
+            Point3::new(0.0, 2.0, -3.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(1.5, 0.0, 1.0),
+        ];
+        let faces = (0..3)
+            .map(|face| [vertex(3 * face), vertex(3 * face + 1), vertex(3 * face + 2)])
+            .collect();
+        let mesh = TriangleMesh::new(
+            positions,
+            Vec::new(),
+            Vec::new(),
+            faces,
+            DiffuseLight::new(Color::new(1.0, 1.0, 1.0), 1.0),
+        );
+        assert!(mesh.is_emissive());
+
+        let light = mesh.light(SplitMethod::Sah).unwrap();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert_samples_match(
+            "mesh light",
+            100_000,
+            |rng| {
+                let mut sampler = Sampler::new(rng.gen(), 0, 0);
+                Some(light.random(origin, &mut sampler).normalize())
+            },
+            |direction| light.pdf_value(origin, direction),
+        );
+    }
+
+    #[test]
+    fn faces_without_area_are_no_light() {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)];
+        let faces = vec![[vertex(0), vertex(1), vertex(1)]];
+        let mesh = TriangleMesh::new(
+            positions,
+            Vec::new(),
+            Vec::new(),
+            faces,
+            DiffuseLight::new(Color::new(1.0, 1.0, 1.0), 1.0),
+        );
+        assert!(mesh.light(SplitMethod::Sah).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+//! GGX (Trowbridge-Reitz) microfacet distribution. Directions are given in a local frame
+//! whose z axis is the surface normal.
+use crate::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+
+/// Below this roughness the distribution is treated as a perfect mirror
+const MIN_ALPHA: f64 = 1.0e-3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// Takes the perceptual roughness in [0, 1], the distribution width is its square
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        Ggx {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < MIN_ALPHA
+    }
+
+    /// Density of microfacet normals `h` per unit projected area
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// Fraction of the microfacets facing `w` that are not masked
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated masking and shadowing
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density with which `sample_visible_normal` returns `h` for the view direction `wo`
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+
+    /// Samples a microfacet normal visible from `wo` (Heitz 2018), `wo` must be above the
+    /// surface and `u1`, `u2` uniform in [0, 1)
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view direction to the configuration of a hemisphere
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalize();
+
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // Uniform point on the projected half disk
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(0.0)).normalize()
+    }
+
+    /// Reflection BRDF times the cosine of `wi`, without the Fresnel term, which is evaluated
+    /// at `wo.dot(h)` for `h = (wo + wi).normalize()`
+    pub fn reflection(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        // The cosine of the light direction cancels against the BRDF's denominator
+        self.d(h) * self.g2(wo, wi) / (4.0 * wo.z)
+    }
+
+    /// Density of `wi` when reflecting `wo` about a sampled visible normal
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        self.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h))
+    }
+}
+
+/// Mirror direction of `wo` about `h`
+pub fn reflect(wo: Vec3, h: Vec3) -> Vec3 {
+    2.0 * wo.dot(h) * h - wo
+}
+
+/// Refracts `wo` through a facet with normal `h` on its side, `eta` is the index of the far
+/// side relative to the near one. Returns `None` on total internal reflection.
+pub fn refract(wo: Vec3, h: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = wo.dot(h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * h)
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, `eta` as for `refract`
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex index of refraction
+/// `eta + i k`, evaluated per color channel
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos = cos_i.clamp(0.0, 1.0);
+        let cos2 = cos * cos;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chi_square::assert_samples_match;
+    use rand::Rng;
+
+    const VIEWS: [(f64, f64, f64); 3] = [(0.0, 0.0, 1.0), (0.6, 0.3, 0.7), (-0.2, 0.95, 0.1)];
+
+    #[test]
+    fn visible_normal_sampling() {
+        for &roughness in &[0.2, 0.5, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            for &(x, y, z) in &VIEWS {
+                let wo = Vec3::new(x, y, z).normalize();
+                assert_samples_match(
+                    &format!("visible normals, roughness {}", roughness),
+                    50_000,
+                    |rng| Some(ggx.sample_visible_normal(wo, rng.gen(), rng.gen())),
+                    |h| ggx.visible_normal_pdf(wo, h),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reflection_sampling() {
+        for &roughness in &[0.2, 0.5, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            for &(x, y, z) in &VIEWS {
+                let wo = Vec3::new(x, y, z).normalize();
+                assert_samples_match(
+                    &format!("reflection, roughness {}", roughness),
+                    50_000,
+                    |rng| {
+                        let h = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+                        Some(reflect(wo, h)).filter(|wi| wi.z > 0.0)
+                    },
+                    |wi| ggx.reflection_pdf(wo, wi),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::hit_sphere;
+use crate::vec3::{Point3, Vec3};
+
+/// Sphere whose center moves linearly from `center0` at `time0` to `center1` at `time1`,
+/// and rests at either end outside of that interval
+pub struct MovingSphere {
+    center0: Point3,
+    center1: Point3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Box<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        (center0, time0): (Point3, f64),
+        (center1, time1): (Point3, f64),
+        radius: f64,
+        material: impl Material + 'static,
+    ) -> Self {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material: Box::new(material),
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + fraction * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            self.center(ray.time),
+            self.radius,
+            Some(self.material.as_ref()),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        let radius = Vec3::new(r, r, r);
+        let box0 = Aabb::new(self.center0 - radius, self.center0 + radius);
+        let box1 = Aabb::new(self.center1 - radius, self.center1 + radius);
+        Some(Aabb::surrounding_box(box0, box1))
+    }
+}
@@ -0,0 +1,682 @@
+use crate::bvh::SplitMethod;
+use crate::hittable_list::HittableList;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::{MeshVertex, TriangleMesh};
+use crate::triangle::Uv;
+use crate::vec3::{Color, Point3, Vec3};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Tracks the current file and line so that every error can point at its origin
+struct Cursor<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl Cursor<'_> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn floats<const N: usize>(&self, keyword: &str, args: &[&str]) -> Result<[f64; N], ObjError> {
+        if args.len() < N {
+            return Err(self.error(format!(
+                "'{}' expects {} numbers, found {}",
+                keyword,
+                N,
+                args.len()
+            )));
+        }
+
+        let mut values = [0.0; N];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = arg
+                .parse()
+                .map_err(|_| self.error(format!("invalid number '{}' in '{}'", arg, keyword)))?;
+        }
+        Ok(values)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Statements of a material library that map onto our materials
+#[derive(Clone, Debug)]
+struct MtlMaterial {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ns: f64,
+    ni: f64,
+    d: f64,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::default(),
+            ke: Color::default(),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+        }
+    }
+}
+
+/// Which of our materials an MTL material maps onto
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MtlKind {
+    Light { emission: Color },
+    Dielectric { ir: f64 },
+    Metal { albedo: Color, fuzz: f64 },
+    Lambertian { albedo: Color },
+}
+
+impl MtlMaterial {
+    fn kind(&self) -> MtlKind {
+        let max_ks = self.ks.x.max(self.ks.y).max(self.ks.z);
+        let max_kd = self.kd.x.max(self.kd.y).max(self.kd.z);
+
+        if self.ke.x.max(self.ke.y).max(self.ke.z) > 0.0 {
+            MtlKind::Light { emission: self.ke }
+        } else if self.d < 1.0 {
+            MtlKind::Dielectric { ir: self.ni }
+        } else if max_ks > 0.0 && max_ks >= max_kd {
+            // Convert the Phong exponent into a roughness-like fuzz factor
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+            MtlKind::Metal {
+                albedo: self.ks,
+                fuzz,
+            }
+        } else {
+            MtlKind::Lambertian { albedo: self.kd }
+        }
+    }
+
+    fn to_material(&self) -> Arc<dyn Material> {
+        match self.kind() {
+            MtlKind::Light { emission } => Arc::new(DiffuseLight::new(emission, 1.0)),
+            MtlKind::Dielectric { ir } => Arc::new(Dielectric::new(ir)),
+            MtlKind::Metal { albedo, fuzz } => Arc::new(Metal::new(albedo, fuzz)),
+            MtlKind::Lambertian { albedo } => Arc::new(Lambertian::new(albedo)),
+        }
+    }
+}
+
+fn parse_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let source = read_file(path)?;
+    Ok(parse_mtl_source(path, &source)?
+        .into_iter()
+        .map(|(name, material)| (name, material.to_material()))
+        .collect())
+}
+
+/// The materials of the MTL file at `path`, whose contents are `source`
+fn parse_mtl_source(path: &Path, source: &str) -> Result<Vec<(String, MtlMaterial)>, ObjError> {
+    let mut cursor = Cursor { path, line: 0 };
+    let mut parsed: Vec<(String, MtlMaterial)> = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        cursor.line = index + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = args
+                .first()
+                .ok_or_else(|| cursor.error("'newmtl' without a name"))?;
+            parsed.push((name.to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let current = match parsed.last_mut() {
+            Some((_, material)) => material,
+            None => return Err(cursor.error(format!("'{}' before any 'newmtl'", keyword))),
+        };
+
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = cursor.floats("Kd", &args)?;
+                current.kd = Color::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = cursor.floats("Ks", &args)?;
+                current.ks = Color::new(r, g, b);
+            }
+            "Ke" => {
+                let [r, g, b] = cursor.floats("Ke", &args)?;
+                current.ke = Color::new(r, g, b);
+            }
+            "Ns" => [current.ns] = cursor.floats("Ns", &args)?,
+            "Ni" => [current.ni] = cursor.floats("Ni", &args)?,
+            "d" => [current.d] = cursor.floats("d", &args)?,
+            "Tr" => {
+                let [tr] = cursor.floats("Tr", &args)?;
+                current.d = 1.0 - tr;
+            }
+            // Everything else (ambient, maps, illumination models) has no equivalent yet
+            _ => {}
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Faces sharing a group and a material, they become one mesh
+struct FaceBatch {
+    material: Arc<dyn Material>,
+    faces: Vec<[MeshVertex; 3]>,
+}
+
+impl FaceBatch {
+    fn into_mesh(self, positions: &[Point3], normals: &[Vec3], uvs: &[Uv]) -> TriangleMesh {
+        // Only copy the vertices this batch references, remapping the indices
+        fn remap<T: Copy>(
+            index: Option<usize>,
+            source: &[T],
+            target: &mut Vec<T>,
+            map: &mut HashMap<usize, usize>,
+        ) -> Option<usize> {
+            index.map(|i| {
+                *map.entry(i).or_insert_with(|| {
+                    target.push(source[i]);
+                    target.len() - 1
+                })
+            })
+        }
+
+        let mut mesh_positions = Vec::new();
+        let mut mesh_normals = Vec::new();
+        let mut mesh_uvs = Vec::new();
+        let mut position_map = HashMap::new();
+        let mut normal_map = HashMap::new();
+        let mut uv_map = HashMap::new();
+
+        let faces = self
+            .faces
+            .iter()
+            .map(|face| {
+                face.map(|vertex| MeshVertex {
+                    position: remap(
+                        Some(vertex.position),
+                        positions,
+                        &mut mesh_positions,
+                        &mut position_map,
+                    )
+                    .unwrap(),
+                    normal: remap(vertex.normal, normals, &mut mesh_normals, &mut normal_map),
+                    uv: remap(vertex.uv, uvs, &mut mesh_uvs, &mut uv_map),
+                })
+            })
+            .collect();
+
+        TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, faces, self.material)
+    }
+}
+
+/// Resolves a 1-based (or negative, relative to the end) OBJ index
+fn resolve_index(cursor: &Cursor, token: &str, len: usize, what: &str) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| cursor.error(format!("invalid {} index '{}'", what, token)))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(cursor.error(format!(
+            "{} index {} out of range, {} defined so far",
+            what, index, len
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_face_vertex(
+    cursor: &Cursor,
+    token: &str,
+    lens: (usize, usize, usize),
+) -> Result<MeshVertex, ObjError> {
+    let mut parts = token.split('/');
+    let position = resolve_index(cursor, parts.next().unwrap_or(""), lens.0, "vertex")?;
+    let uv = match parts.next() {
+        Some(t) if !t.is_empty() => Some(resolve_index(cursor, t, lens.1, "texture coordinate")?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(n) if !n.is_empty() => Some(resolve_index(cursor, n, lens.2, "normal")?),
+        _ => None,
+    };
+
+    if parts.next().is_some() {
+        return Err(cursor.error(format!("malformed face vertex '{}'", token)));
+    }
+
+    Ok(MeshVertex {
+        position,
+        normal,
+        uv,
+    })
+}
+
+/// Loads a Wavefront OBJ file and its material libraries. Every group and material
+/// combination becomes its own mesh, polygons are triangulated as fans. Materials with an
+/// emission `Ke` become diffuse lights.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<HittableList, ObjError> {
+    let mut world = HittableList::new();
+    for mesh in load_meshes(path.as_ref(), &mut Vec::new())? {
+        world.add(mesh.into_bvh(SplitMethod::Sah));
+    }
+
+    Ok(world)
+}
+
+/// The meshes `load_obj` makes of a file, adding the paths of the material libraries it
+/// read to `libraries`
+pub(crate) fn load_meshes(
+    path: &Path,
+    libraries: &mut Vec<PathBuf>,
+) -> Result<Vec<TriangleMesh>, ObjError> {
+    let source = read_file(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let ObjContents {
+        positions,
+        normals,
+        uvs,
+        batches,
+    } = parse_obj(path, &source, |library| {
+        let library = directory.join(library);
+        let materials = parse_mtl(&library)?;
+        libraries.push(library);
+        Ok(materials)
+    })?;
+
+    Ok(batches
+        .into_iter()
+        .map(|(_, batch)| batch.into_mesh(&positions, &normals, &uvs))
+        .collect())
+}
+
+/// Vertex data and faces of an OBJ file, before they become meshes
+struct ObjContents {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Uv>,
+    /// By group and material name, in the order of their first face
+    batches: Vec<((String, Option<String>), FaceBatch)>,
+}
+
+/// Parses the OBJ file at `path`, whose contents are `source`. `load_library` reads the
+/// material library of an `mtllib` statement by the name it is given.
+fn parse_obj(
+    path: &Path,
+    source: &str,
+    mut load_library: impl FnMut(&str) -> Result<HashMap<String, Arc<dyn Material>>, ObjError>,
+) -> Result<ObjContents, ObjError> {
+    let mut cursor = Cursor { path, line: 0 };
+
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<Uv> = Vec::new();
+
+    let mut group = String::new();
+    let mut material_name: Option<String> = None;
+    let mut material = default_material;
+    // Keyed by group and material so that revisiting a group appends to the same mesh
+    let mut batches: Vec<((String, Option<String>), FaceBatch)> = Vec::new();
+    let mut batch_indices: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    for (index, line) in source.lines().enumerate() {
+        cursor.line = index + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = cursor.floats("v", &args)?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = cursor.floats("vn", &args)?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                let u = cursor.floats::<1>("vt", &args)?[0];
+                let v = if args.len() > 1 {
+                    cursor.floats::<1>("vt", &args[1..])?[0]
+                } else {
+                    0.0
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(cursor.error(format!(
+                        "face needs at least 3 vertices, found {}",
+                        args.len()
+                    )));
+                }
+
+                let lens = (positions.len(), uvs.len(), normals.len());
+                let vertices = args
+                    .iter()
+                    .map(|token| parse_face_vertex(&cursor, token, lens))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let key = (group.clone(), material_name.clone());
+                let index = *batch_indices.entry(key.clone()).or_insert_with(|| {
+                    batches.push((
+                        key,
+                        FaceBatch {
+                            material: material.clone(),
+                            faces: Vec::new(),
+                        },
+                    ));
+                    batches.len() - 1
+                });
+                let batch = &mut batches[index].1;
+
+                for i in 1..vertices.len() - 1 {
+                    batch
+                        .faces
+                        .push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            }
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => {
+                let name = args
+                    .first()
+                    .ok_or_else(|| cursor.error("'usemtl' without a material name"))?;
+                material = materials
+                    .get(*name)
+                    .cloned()
+                    .ok_or_else(|| cursor.error(format!("unknown material '{}'", name)))?;
+                material_name = Some(name.to_string());
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(cursor.error("'mtllib' without a file name"));
+                }
+                for library in &args {
+                    materials.extend(load_library(library)?);
+                }
+            }
+            // Smoothing groups, free-form geometry and the like are not supported
+            _ => {}
+        }
+    }
+
+    Ok(ObjContents {
+        positions,
+        normals,
+        uvs,
+        batches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<ObjContents, ObjError> {
+        parse_obj(Path::new("mesh.obj"), source, |library| {
+            panic!("unexpected material library '{}'", library)
+        })
+    }
+
+    fn vertex(position: usize, uv: Option<usize>, normal: Option<usize>) -> MeshVertex {
+        MeshVertex {
+            position,
+            normal,
+            uv,
+        }
+    }
+
+    fn positions(face: &[MeshVertex; 3]) -> [usize; 3] {
+        face.map(|vertex| vertex.position)
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let message = |source: &str| parse(source).err().unwrap().to_string();
+        assert_eq!(
+            message("v 0 0 0\nv 1 0\n"),
+            "mesh.obj:2: 'v' expects 3 numbers, found 2"
+        );
+        assert_eq!(
+            message("v 0 0 0\n# comment\nv 1 0 x\n"),
+            "mesh.obj:3: invalid number 'x' in 'v'"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n"),
+            "mesh.obj:5: vertex index 4 out of range, 3 defined so far"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nf 1 2\n"),
+            "mesh.obj:3: face needs at least 3 vertices, found 2"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 0\n"),
+            "mesh.obj:4: vertex index 0 out of range, 3 defined so far"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2 3\n"),
+            "mesh.obj:4: texture coordinate index 1 out of range, 0 defined so far"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/// 2 3\n"),
+            "mesh.obj:4: malformed face vertex '1///'"
+        );
+        assert_eq!(
+            message("usemtl red\n"),
+            "mesh.obj:1: unknown material 'red'"
+        );
+
+        let mtl = parse_mtl_source(Path::new("mesh.mtl"), "# materials\nKd 1 0 0\n");
+        assert_eq!(
+            mtl.err().unwrap().to_string(),
+            "mesh.mtl:2: 'Kd' before any 'newmtl'"
+        );
+    }
+
+    #[test]
+    fn indices_count_from_one_or_back_from_the_end() {
+        let contents = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n\
+             v 1 1 0\nf -4 -3 -1\n",
+        )
+        .unwrap();
+        let faces = &contents.batches[0].1.faces;
+        assert_eq!(positions(&faces[0]), [0, 1, 2]);
+        assert_eq!(positions(&faces[1]), [0, 1, 3]);
+    }
+
+    #[test]
+    fn face_vertices_take_every_index_form() {
+        let contents = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 0 1\n\
+             vn 0 0 1\nvn 0 0 1\nvn 0 0 1\n\
+             f 1/1/1 2/2/2 3/3/3\n\
+             f 1//1 2//2 3//3\n\
+             f 1/1 2/2 3/3\n\
+             f 1 2 3\n",
+        )
+        .unwrap();
+        assert_eq!(contents.uvs, vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        assert_eq!(contents.normals.len(), 3);
+
+        let faces = &contents.batches[0].1.faces;
+        assert_eq!(faces[0][1], vertex(1, Some(1), Some(1)));
+        assert_eq!(faces[1][1], vertex(1, None, Some(1)));
+        assert_eq!(faces[2][1], vertex(1, Some(1), None));
+        assert_eq!(faces[3][1], vertex(1, None, None));
+    }
+
+    #[test]
+    fn polygons_become_fans() {
+        let contents = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+        let faces: Vec<_> = contents.batches[0].1.faces.iter().map(positions).collect();
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn groups_and_materials_split_the_faces() {
+        let source = "mtllib shiny.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             f 1 2 3\n\
+             g lid\nf 1 2 3\n\
+             usemtl chrome\nf 1 2 3\n\
+             g body\nf 1 2 3\n\
+             g lid\nf 1 2 3\n";
+        let mut libraries = Vec::new();
+        let contents = parse_obj(Path::new("mesh.obj"), source, |library| {
+            libraries.push(library.to_string());
+            let mtl = parse_mtl_source(Path::new(library), "newmtl chrome\nKs 1 1 1\n")?;
+            Ok(mtl
+                .into_iter()
+                .map(|(name, material)| (name, material.to_material()))
+                .collect())
+        })
+        .unwrap();
+        assert_eq!(libraries, vec!["shiny.mtl"]);
+
+        let batches: Vec<_> = contents
+            .batches
+            .iter()
+            .map(|((group, material), batch)| {
+                (group.as_str(), material.as_deref(), batch.faces.len())
+            })
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                ("", None, 1),
+                ("lid", None, 1),
+                ("lid", Some("chrome"), 2),
+                ("body", Some("chrome"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn mtl_materials_map_onto_ours() {
+        let source = "newmtl glass\nKd 1 1 1\nNi 1.5\nd 0.5\n\
+             newmtl frosted\nNi 1.3\nTr 0.2\n\
+             newmtl steel\nKd 0.2 0.2 0.2\nKs 0.8 0.7 0.6\nNs 98\n\
+             newmtl mirror\nKd 0 0 0\nKs 1 1 1\nNs 0\n\
+             newmtl paint\nKd 0.8 0.1 0.1\nKs 0.5 0.5 0.5\n\
+             newmtl lamp\nKd 0.5 0.5 0.5\nKe 4 3 2\n\
+             newmtl plain\n";
+        let kinds: Vec<_> = parse_mtl_source(Path::new("mesh.mtl"), source)
+            .unwrap()
+            .into_iter()
+            .map(|(name, material)| (name, material.kind()))
+            .collect();
+        let expected = vec![
+            ("glass", MtlKind::Dielectric { ir: 1.5 }),
+            ("frosted", MtlKind::Dielectric { ir: 1.3 }),
+            (
+                "steel",
+                MtlKind::Metal {
+                    albedo: Color::new(0.8, 0.7, 0.6),
+                    fuzz: 0.02f64.sqrt(),
+                },
+            ),
+            (
+                "mirror",
+                MtlKind::Metal {
+                    albedo: Color::new(1.0, 1.0, 1.0),
+                    fuzz: 1.0,
+                },
+            ),
+            (
+                "paint",
+                MtlKind::Lambertian {
+                    albedo: Color::new(0.8, 0.1, 0.1),
+                },
+            ),
+            (
+                "lamp",
+                MtlKind::Light {
+                    emission: Color::new(4.0, 3.0, 2.0),
+                },
+            ),
+            (
+                "plain",
+                MtlKind::Lambertian {
+                    albedo: Color::new(0.8, 0.8, 0.8),
+                },
+            ),
+        ];
+        assert_eq!(kinds.len(), expected.len());
+        for ((name, kind), (expected_name, expected_kind)) in kinds.iter().zip(&expected) {
+            assert_eq!(name, expected_name);
+            assert_eq!(kind, expected_kind, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::color::{quantize, to_display, write_color};
+use crate::vec3::Color;
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::openexr::OpenExrEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder as _, ImageError, Rgb};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::Path;
+
+/// Averaged linear radiance of every pixel, stored row by row from the top
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(io::Error),
+    Encoding(ImageError),
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Io(err) => write!(f, "{}", err),
+            OutputError::Encoding(err) => write!(f, "{}", err),
+            OutputError::UnsupportedFormat(extension) => write!(
+                f,
+                "unsupported image format '{}', expected .ppm, .png, .hdr or .exr",
+                extension
+            ),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OutputError::Io(err) => Some(err),
+            OutputError::Encoding(err) => Some(err),
+            OutputError::UnsupportedFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(err: io::Error) -> Self {
+        OutputError::Io(err)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(err: ImageError) -> Self {
+        OutputError::Encoding(err)
+    }
+}
+
+pub trait ImageEncoder: Send + Sync {
+    fn encode(&self, image: &Framebuffer, stream: &mut dyn Write) -> Result<(), OutputError>;
+}
+
+/// Plain text PPM, one pixel per line
+pub struct PpmAscii;
+
+impl ImageEncoder for PpmAscii {
+    fn encode(&self, image: &Framebuffer, mut stream: &mut dyn Write) -> Result<(), OutputError> {
+        writeln!(stream, "P3\n{} {}\n255", image.width, image.height)?;
+        for pixel_color in &image.pixels {
+            write_color(&mut stream, *pixel_color, 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Binary PPM with 8 bits per channel
+pub struct PpmBinary;
+
+impl ImageEncoder for PpmBinary {
+    fn encode(&self, image: &Framebuffer, stream: &mut dyn Write) -> Result<(), OutputError> {
+        write!(stream, "P6\n{} {}\n255\n", image.width, image.height)?;
+        stream.write_all(&to_rgb8(image))?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+pub struct Png {
+    pub bit_depth: BitDepth,
+}
+
+impl ImageEncoder for Png {
+    fn encode(&self, image: &Framebuffer, stream: &mut dyn Write) -> Result<(), OutputError> {
+        let encoder = PngEncoder::new(stream);
+        let (width, height) = (image.width as u32, image.height as u32);
+
+        match self.bit_depth {
+            BitDepth::Eight => {
+                encoder.write_image(&to_rgb8(image), width, height, ExtendedColorType::Rgb8)?
+            }
+            BitDepth::Sixteen => {
+                // The encoder expects native endian samples
+                let bytes: Vec<u8> = image
+                    .pixels
+                    .iter()
+                    .flat_map(|c| {
+                        let c = to_display(*c);
+                        [c.x, c.y, c.z]
+                    })
+                    .flat_map(|channel| quantize(channel, u16::MAX).to_ne_bytes())
+                    .collect();
+                encoder.write_image(&bytes, width, height, ExtendedColorType::Rgb16)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Radiance RGBE, keeps the un-tonemapped linear radiance
+pub struct RadianceHdr;
+
+impl ImageEncoder for RadianceHdr {
+    fn encode(&self, image: &Framebuffer, stream: &mut dyn Write) -> Result<(), OutputError> {
+        let pixels: Vec<Rgb<f32>> = image
+            .pixels
+            .iter()
+            .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+            .collect();
+        HdrEncoder::new(stream).encode(&pixels, image.width, image.height)?;
+        Ok(())
+    }
+}
+
+/// OpenEXR with 32-bit float channels, keeps the un-tonemapped linear radiance
+pub struct OpenExr;
+
+impl ImageEncoder for OpenExr {
+    fn encode(&self, image: &Framebuffer, stream: &mut dyn Write) -> Result<(), OutputError> {
+        let bytes: Vec<u8> = image
+            .pixels
+            .iter()
+            .flat_map(|c| [c.x as f32, c.y as f32, c.z as f32])
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+
+        // The EXR writer needs to seek, so the file is assembled in memory first
+        let mut buffer = Cursor::new(Vec::new());
+        OpenExrEncoder::new(&mut buffer).write_image(
+            &bytes,
+            image.width as u32,
+            image.height as u32,
+            ExtendedColorType::Rgb32F,
+        )?;
+        stream.write_all(buffer.get_ref())?;
+        Ok(())
+    }
+}
+
+fn to_rgb8(image: &Framebuffer) -> Vec<u8> {
+    image
+        .pixels
+        .iter()
+        .flat_map(|c| {
+            let c = to_display(*c);
+            [c.x, c.y, c.z]
+        })
+        .map(|channel| quantize(channel, u8::MAX as u16) as u8)
+        .collect()
+}
+
+/// Picks the encoder from the file extension
+pub fn encoder_for_path(
+    path: &Path,
+    bit_depth: BitDepth,
+) -> Result<Box<dyn ImageEncoder>, OutputError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "ppm" => Ok(Box::new(PpmBinary)),
+        "png" => Ok(Box::new(Png { bit_depth })),
+        "hdr" => Ok(Box::new(RadianceHdr)),
+        "exr" => Ok(Box::new(OpenExr)),
+        _ => Err(OutputError::UnsupportedFormat(extension)),
+    }
+}
+
+/// Writes `image` to `path` through a temporary file next to it, so that an image rewritten
+/// while a render progresses is never seen half written
+pub fn write_file(
+    encoder: &dyn ImageEncoder,
+    image: &Framebuffer,
+    path: &Path,
+) -> Result<(), OutputError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".partial");
+
+    let mut stream = BufWriter::new(File::create(&temporary)?);
+    encoder.encode(image, &mut stream)?;
+    stream.flush()?;
+    drop(stream);
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat};
+
+    /// Three by two pixels, linear radiance from black to above white
+    fn image() -> Framebuffer {
+        let mut image = Framebuffer::new(3, 2);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = Color::new(0.1 * i as f64, 0.25, 0.5 + 0.4 * i as f64);
+        }
+        image
+    }
+
+    fn encode(encoder: &dyn ImageEncoder) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        encoder.encode(&image(), &mut bytes).unwrap();
+        bytes
+    }
+
+    fn decode(bytes: &[u8], format: ImageFormat) -> DynamicImage {
+        let decoded = image::load_from_memory_with_format(bytes, format).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+        decoded
+    }
+
+    #[test]
+    fn ppm() {
+        let bytes = encode(&PpmBinary);
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], to_rgb8(&image()).as_slice());
+
+        let text = String::from_utf8(encode(&PpmAscii)).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.by_ref().take(3).collect::<Vec<_>>(),
+            ["P3", "3 2", "255"]
+        );
+        assert_eq!(lines.count(), 6);
+    }
+
+    #[test]
+    fn png() {
+        let decoded = decode(
+            &encode(&Png {
+                bit_depth: BitDepth::Eight,
+            }),
+            ImageFormat::Png,
+        );
+        assert_eq!(decoded.as_bytes(), to_rgb8(&image()).as_slice());
+
+        let decoded = decode(
+            &encode(&Png {
+                bit_depth: BitDepth::Sixteen,
+            }),
+            ImageFormat::Png,
+        );
+        let pixels = decoded.as_rgb16().unwrap();
+        for (decoded, pixel) in pixels.pixels().zip(&image().pixels) {
+            let expected = to_display(*pixel);
+            for (channel, expected) in decoded.0.iter().zip(&[expected.x, expected.y, expected.z]) {
+                assert_eq!(*channel, quantize(*expected, u16::MAX));
+            }
+        }
+    }
+
+    #[test]
+    fn hdr() {
+        let bytes = encode(&RadianceHdr);
+        assert!(bytes.starts_with(b"#?RADIANCE"));
+        let decoded = decode(&bytes, ImageFormat::Hdr).into_rgb32f();
+        for (decoded, pixel) in decoded.pixels().zip(&image().pixels) {
+            // RGBE keeps 8 bits of mantissa shared by the channels
+            for (channel, expected) in decoded.0.iter().zip(&[pixel.x, pixel.y, pixel.z]) {
+                assert!((*channel as f64 - expected).abs() < 0.01, "{:?}", decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn exr() {
+        let bytes = encode(&OpenExr);
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        let decoded = decode(&bytes, ImageFormat::OpenExr).into_rgb32f();
+        for (decoded, pixel) in decoded.pixels().zip(&image().pixels) {
+            let expected = [pixel.x as f32, pixel.y as f32, pixel.z as f32];
+            assert_eq!(decoded.0, expected);
+        }
+    }
+
+    #[test]
+    fn encoders_by_extension() {
+        let encoder = |path: &str| encoder_for_path(Path::new(path), BitDepth::Eight);
+        let mut bytes = Vec::new();
+        encoder("image.PNG")
+            .unwrap()
+            .encode(&image(), &mut bytes)
+            .unwrap();
+        assert!(bytes.starts_with(b"\x89PNG"));
+        for path in &["image.ppm", "image.hdr", "image.exr"] {
+            assert!(encoder(path).is_ok(), "{}", path);
+        }
+
+        for (path, extension) in &[("image.bmp", "bmp"), ("image", ""), ("png", "")] {
+            match encoder(path).err() {
+                Some(OutputError::UnsupportedFormat(found)) => assert_eq!(found, *extension),
+                _ => panic!("{} was accepted", path),
+            }
+        }
+    }
+}
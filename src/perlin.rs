@@ -0,0 +1,95 @@
+use crate::vec3::{Point3, Vec3};
+use rand::seq::SliceRandom;
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise over random unit vectors at the lattice points
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut impl rand::Rng) -> Perlin {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::vec3_random_range(rng, -1.0..1.0).normalize())
+            .collect();
+
+        Perlin {
+            gradients,
+            perm_x: Perlin::permutation(rng),
+            perm_y: Perlin::permutation(rng),
+            perm_z: Perlin::permutation(rng),
+        }
+    }
+
+    fn permutation(rng: &mut impl rand::Rng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        p.shuffle(rng);
+        p
+    }
+
+    /// Smooth noise in roughly [-1, 1]
+    pub fn noise(&self, p: Point3) -> f64 {
+        let (i, j, k) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - i, p.y - j, p.z - k);
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[Perlin::wrap(i + di as i64)]
+                        ^ self.perm_y[Perlin::wrap(j + dj as i64)]
+                        ^ self.perm_z[Perlin::wrap(k + dk as i64)];
+                    *gradient = self.gradients[index];
+                }
+            }
+        }
+
+        Perlin::interpolate(&c, u, v, w)
+    }
+
+    /// Sum of `depth` octaves of noise, each at twice the frequency and half the weight
+    pub fn turbulence(&self, p: Point3, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+
+        accum.abs()
+    }
+
+    fn wrap(i: i64) -> usize {
+        (i & (POINT_COUNT as i64 - 1)) as usize
+    }
+
+    fn interpolate(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Hermite smoothing hides the lattice
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(weight);
+                }
+            }
+        }
+        accum
+    }
+}
@@ -0,0 +1,54 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// Infinite plane through `point`. It has no bounding box, so scenes keep it outside the BVH.
+pub struct Plane {
+    point: Point3,
+    /// Orthonormal basis whose `w` is the unit normal, `u` and `v` span the plane
+    frame: Onb,
+    material: Box<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: impl Material + 'static) -> Self {
+        Plane {
+            point,
+            frame: Onb::from_w(normal),
+            material: Box::new(material),
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let normal = self.frame.w;
+        let denominator = ray.direction.dot(normal);
+        // Ray parallel to the plane
+        if denominator.abs() < 1.0e-12 {
+            return None;
+        }
+
+        let t = (self.point - ray.origin).dot(normal) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let mut rec = HitRecord::new(ray.at(t), t, Some(self.material.as_ref()));
+        rec.set_face_normal(ray, normal);
+
+        // Textures repeat every unit along the two in-plane axes
+        let offset = rec.p - self.point;
+        rec.u = offset.dot(self.frame.u).rem_euclid(1.0);
+        rec.v = offset.dot(self.frame.v).rem_euclid(1.0);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
@@ -4,16 +4,28 @@ use crate::vec3::{Point3, Vec3};
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    /// Instant within the camera shutter interval the ray travels at
+    pub time: f64,
+    /// Random bits for the media the ray passes through, drawn from the sampler of the
+    /// camera sample for every ray of a path, see `ConstantMedium`
+    pub medium_seed: u64,
 }
 
 impl Ray {
-    pub fn new(orig: Point3, dir: Vec3) -> Ray {
+    pub fn new(orig: Point3, dir: Vec3, time: f64) -> Ray {
         Ray {
             origin: orig,
             direction: dir,
+            time,
+            medium_seed: 0,
         }
     }
 
+    pub fn with_medium_seed(mut self, seed: u64) -> Ray {
+        self.medium_seed = seed;
+        self
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
@@ -0,0 +1,473 @@
+use crate::camera::Camera;
+use crate::color::luminance;
+use crate::distributed::DistributedError;
+use crate::integrator::ray_color;
+use crate::output::Framebuffer;
+use crate::sampler::Sampler;
+use crate::scene::{RenderSettings, Scene};
+use crate::vec3::Color;
+use rand::Rng;
+use rayon::prelude::*;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Side length in pixels of the square tiles the image is rendered in, unless the renderer
+/// is given another
+pub const DEFAULT_TILE_SIZE: usize = 32;
+
+/// Running sums of the samples of one pixel
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelSums {
+    pub color: Color,
+    /// Sum of the squared luminances of the samples, for their variance
+    pub luminance_squares: f64,
+    pub samples: i32,
+}
+
+impl PixelSums {
+    fn add(&mut self, sample: Color) {
+        self.color += sample;
+        self.luminance_squares += luminance(sample).powi(2);
+        self.samples += 1;
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself. Black pixels count
+    /// as having a mean of 1e-3, so they converge once they stay about that dark.
+    fn relative_error(&self) -> f64 {
+        let n = self.samples as f64;
+        let mean = luminance(self.color) / n;
+        let variance = (self.luminance_squares / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() / mean.max(1.0e-3)
+    }
+
+    fn is_converged(&self, settings: &RenderSettings) -> bool {
+        match settings.adaptive_threshold {
+            Some(threshold) => {
+                self.samples >= settings.min_samples_per_pixel.max(2)
+                    && self.relative_error() < threshold
+            }
+            None => false,
+        }
+    }
+}
+
+/// Sample sums of a whole image, row by row from the top
+#[derive(Clone, Debug)]
+pub struct Accumulation {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<PixelSums>,
+}
+
+impl Accumulation {
+    pub fn new(width: usize, height: usize) -> Accumulation {
+        Accumulation {
+            width,
+            height,
+            pixels: vec![PixelSums::default(); width * height],
+        }
+    }
+
+    /// Mean of the samples of every pixel, black where there are none yet
+    pub fn average(&self) -> Framebuffer {
+        let mut image = Framebuffer::new(self.width, self.height);
+        for (pixel, sums) in image.pixels.iter_mut().zip(&self.pixels) {
+            if sums.samples > 0 {
+                *pixel = sums.color / sums.samples as f64;
+            }
+        }
+        image
+    }
+
+    /// Gray image of the samples every pixel got, white for `max_samples`
+    pub fn sample_map(&self, max_samples: i32) -> Framebuffer {
+        let mut image = Framebuffer::new(self.width, self.height);
+        for (pixel, sums) in image.pixels.iter_mut().zip(&self.pixels) {
+            let fraction = sums.samples as f64 / max_samples as f64;
+            *pixel = Color::new(fraction, fraction, fraction);
+        }
+        image
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|sums| sums.samples as u64).sum()
+    }
+
+    /// Copies the sums of the pixels of `tile`, row by row
+    pub fn tile(&self, tile: &Tile) -> Vec<PixelSums> {
+        (tile.y..tile.y + tile.height)
+            .flat_map(|y| {
+                let start = y * self.width + tile.x;
+                self.pixels[start..start + tile.width].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Replaces the sums of the pixels of `tile` with `sums`, given row by row
+    pub fn set_tile(&mut self, tile: &Tile, sums: &[PixelSums]) {
+        for (row, sums) in sums.chunks(tile.width).enumerate() {
+            let start = (tile.y + row) * self.width + tile.x;
+            self.pixels[start..start + tile.width].copy_from_slice(sums);
+        }
+    }
+}
+
+/// Rectangle of the image, `y` counts rows from the top
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Shared flag that stops a render, checked before every tile
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Tiles already being rendered still finish, no new ones start
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How a render ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderStatus {
+    Finished,
+    /// Stopped by a `CancelToken`, the accumulation holds every tile finished until then
+    Cancelled,
+}
+
+/// Snapshot of how far a render has come
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Tiles finished in the current pass, out of `tiles`
+    pub tiles_done: usize,
+    pub tiles: usize,
+    /// Camera samples taken so far, out of at most `samples_total`. Adaptive sampling may
+    /// finish before taking them all.
+    pub samples_done: u64,
+    pub samples_total: u64,
+    /// Rays traced against the scene since the render started, shadow rays included
+    pub rays: u64,
+    pub elapsed: Duration,
+    /// Samples taken since the render started, the rest of `samples_done` were there before
+    samples_this_run: u64,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.samples_total == 0 {
+            return 1.0;
+        }
+        self.samples_done as f64 / self.samples_total as f64
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        self.rays as f64 / self.elapsed.as_secs_f64().max(1.0e-9)
+    }
+
+    /// Estimated time to take the remaining samples at the speed so far, `None` until some
+    /// have been taken
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.samples_this_run == 0 {
+            return None;
+        }
+        let remaining = self.samples_total.saturating_sub(self.samples_done);
+        let per_sample = self.elapsed.as_secs_f64() / self.samples_this_run as f64;
+        Some(Duration::from_secs_f64(per_sample * remaining as f64))
+    }
+}
+
+/// Gets told about a render as it goes. The tile methods are called from the render
+/// threads, the pass methods from the thread running the render and the worker methods from
+/// the threads of the coordinator.
+pub trait RenderObserver: Sync {
+    /// Pass `pass` out of `passes` starts, taking `samples` more per pixel
+    fn pass_started(&self, _pass: usize, _passes: usize, _samples: i32) {}
+
+    fn tile_started(&self, _tile: &Tile) {}
+
+    fn tile_finished(&self, _tile: &Tile, _progress: &Progress) {}
+
+    /// A finished tile was added to `accumulation`. No other tile is added meanwhile, so
+    /// every pixel holds the samples of whole tiles, e.g. for a checkpoint. The other tiles
+    /// wait for this to return, anything slow belongs in `tile_finished` on a copy.
+    fn tile_merged(&self, _tile: &Tile, _accumulation: &Accumulation) {}
+
+    /// A pass completed, `accumulation` holds all samples so far
+    fn pass_finished(&self, _pass: usize, _passes: usize, _accumulation: &Accumulation) {}
+
+    /// A worker connected to the coordinator of a distributed render
+    fn worker_connected(&self, _address: SocketAddr) {}
+
+    /// A worker left, after the render or with the error that made the coordinator give up
+    /// on it
+    fn worker_disconnected(&self, _address: SocketAddr, _error: Option<&DistributedError>) {}
+}
+
+/// Observer that ignores everything
+pub struct NoObserver;
+
+impl RenderObserver for NoObserver {}
+
+/// Counters shared by the render threads
+pub(crate) struct Tracker {
+    start: Instant,
+    samples_before: u64,
+    samples_total: u64,
+    samples: AtomicU64,
+    rays: AtomicU64,
+    tiles_done: AtomicUsize,
+}
+
+impl Tracker {
+    /// Starts counting towards `samples_per_pixel` samples in every pixel of `accumulation`
+    pub(crate) fn new(accumulation: &Accumulation, samples_per_pixel: i32) -> Tracker {
+        Tracker {
+            start: Instant::now(),
+            samples_before: accumulation.total_samples(),
+            samples_total: accumulation.pixels.len() as u64 * samples_per_pixel as u64,
+            samples: AtomicU64::new(0),
+            rays: AtomicU64::new(0),
+            tiles_done: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a finished tile
+    pub(crate) fn add_tile(&self, samples: u64, rays: u64) {
+        self.samples.fetch_add(samples, Ordering::Relaxed);
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        self.tiles_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn progress(&self, tiles: usize) -> Progress {
+        let samples_this_run = self.samples.load(Ordering::Relaxed);
+        Progress {
+            tiles_done: self.tiles_done.load(Ordering::Relaxed),
+            tiles,
+            samples_done: self.samples_before + samples_this_run,
+            samples_total: self.samples_total,
+            rays: self.rays.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+            samples_this_run,
+        }
+    }
+}
+
+/// Renders the scene as seen through `camera` with `settings`, whose image size has to
+/// match the camera's aspect ratio
+pub struct Renderer<'a> {
+    scene: &'a Scene,
+    camera: Camera,
+    settings: RenderSettings,
+    observer: &'a dyn RenderObserver,
+    cancel: CancelToken,
+    tile_size: usize,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(scene: &'a Scene, camera: Camera, settings: RenderSettings) -> Renderer<'a> {
+        Renderer {
+            scene,
+            camera,
+            settings,
+            observer: &NoObserver,
+            cancel: CancelToken::default(),
+            tile_size: DEFAULT_TILE_SIZE,
+        }
+    }
+
+    pub fn with_observer(mut self, observer: &'a dyn RenderObserver) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Makes the render stop once `cancel` is cancelled
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Renders in square tiles of `tile_size` pixels a side. Smaller tiles spread the work
+    /// better over the threads, larger ones cost less to hand out. The image is the same
+    /// either way.
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        assert!(tile_size > 0, "tiles need at least one pixel");
+        self.tile_size = tile_size;
+        self
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Renders all samples in one go, or until cancelled
+    pub fn render(&self) -> Framebuffer {
+        let mut accumulation = self.accumulation();
+        self.render_into(&mut accumulation, false);
+        accumulation.average()
+    }
+
+    /// Renders all passes into `accumulation`, see `passes`. Samples already in it, e.g.
+    /// from a checkpoint, are kept, pixels only get the samples they are missing.
+    pub fn render_into(&self, accumulation: &mut Accumulation, progressive: bool) -> RenderStatus {
+        let tracker = Tracker::new(accumulation, self.settings.samples_per_pixel);
+
+        let passes = self.passes(progressive);
+        let mut samples = 0;
+        for (pass, &pass_samples) in passes.iter().enumerate() {
+            samples += pass_samples;
+            self.observer.pass_started(pass, passes.len(), pass_samples);
+            tracker.tiles_done.store(0, Ordering::Relaxed);
+            self.render_pass(accumulation, samples, &tracker);
+            if self.cancel.is_cancelled() {
+                return RenderStatus::Cancelled;
+            }
+            self.observer
+                .pass_finished(pass, passes.len(), accumulation);
+        }
+        RenderStatus::Finished
+    }
+
+    /// Empty sums for the image
+    pub fn accumulation(&self) -> Accumulation {
+        Accumulation::new(
+            self.settings.image_width as usize,
+            self.settings.image_height as usize,
+        )
+    }
+
+    /// Samples per pixel of each pass. A progressive render, and an adaptive one, which
+    /// checks the pixels in between, doubles them from 1 until they add up to
+    /// `samples_per_pixel`.
+    pub fn passes(&self, progressive: bool) -> Vec<i32> {
+        let samples_per_pixel = self.settings.samples_per_pixel;
+        if !progressive && self.settings.adaptive_threshold.is_none() {
+            return vec![samples_per_pixel];
+        }
+
+        let mut passes = Vec::new();
+        let mut total = 0;
+        let mut next = 1;
+        while total < samples_per_pixel {
+            let samples = next.min(samples_per_pixel - total);
+            passes.push(samples);
+            total += samples;
+            next = next.saturating_mul(2);
+        }
+        passes
+    }
+
+    /// The image cut into tiles, see `tiles`
+    pub fn tiles(&self) -> Vec<Tile> {
+        tiles(
+            self.settings.image_width as usize,
+            self.settings.image_height as usize,
+            self.tile_size,
+        )
+    }
+
+    /// Brings the sums of the pixels of `tile`, given row by row, through all passes the
+    /// way `render_into` would, e.g. to render a tile elsewhere. Returns the number of
+    /// samples taken and of rays traced.
+    pub fn render_tile(&self, tile: &Tile, sums: &mut [PixelSums]) -> (u64, u64) {
+        let mut samples = 0;
+        let mut totals = (0, 0);
+        for pass_samples in self.passes(false) {
+            samples += pass_samples;
+            let (samples_taken, rays) = self.sample_tile(tile, sums, samples);
+            totals.0 += samples_taken;
+            totals.1 += rays;
+        }
+        totals
+    }
+
+    /// Brings every pixel up to `samples` samples, rendering the tiles in parallel and
+    /// skipping pixels that adaptive sampling considers converged. Every sample has its own
+    /// random sequence, so the sums come out the same however the samples are split into
+    /// passes.
+    fn render_pass(&self, accumulation: &mut Accumulation, samples: i32, tracker: &Tracker) {
+        let tiles = self.tiles();
+        let accumulation = Mutex::new(accumulation);
+
+        tiles.par_iter().for_each(|tile| {
+            if self.cancel.is_cancelled() {
+                return;
+            }
+            self.observer.tile_started(tile);
+
+            let mut sums = accumulation.lock().unwrap().tile(tile);
+            let (samples_taken, rays) = self.sample_tile(tile, &mut sums, samples);
+
+            {
+                let mut accumulation = accumulation.lock().unwrap();
+                accumulation.set_tile(tile, &sums);
+                self.observer.tile_merged(tile, &accumulation);
+            }
+
+            tracker.add_tile(samples_taken, rays);
+            self.observer
+                .tile_finished(tile, &tracker.progress(tiles.len()));
+        });
+    }
+
+    /// Brings the sums of the pixels of `tile`, given row by row, up to `samples` samples.
+    /// Returns the number of samples taken and of rays traced.
+    fn sample_tile(&self, tile: &Tile, sums: &mut [PixelSums], samples: i32) -> (u64, u64) {
+        let image_width = self.settings.image_width as usize;
+        let image_height = self.settings.image_height as usize;
+        let mut samples_taken = 0;
+        let mut rays = 0;
+
+        for (index, pixel) in sums.iter_mut().enumerate() {
+            // Rows count from the bottom for the camera
+            let i = tile.x + index % tile.width;
+            let j = image_height - 1 - (tile.y + index / tile.width);
+            let pixel_index = (j * image_width + i) as u64;
+
+            if pixel.is_converged(&self.settings) {
+                continue;
+            }
+            for sample in pixel.samples..samples {
+                let mut sampler = Sampler::new(self.settings.seed, pixel_index, sample as u64);
+                let u = (i as f64 + sampler.gen::<f64>()) / image_width as f64;
+                let v = (j as f64 + sampler.gen::<f64>()) / image_height as f64;
+                let ray = self.camera.get_ray(u, v, &mut sampler);
+                let color = ray_color(ray, self.scene, &self.settings, &mut sampler, &mut rays);
+                pixel.add(color);
+                samples_taken += 1;
+            }
+        }
+        (samples_taken, rays)
+    }
+}
+
+/// An image of `width` by `height` pixels cut into tiles of `tile_size` pixels a side,
+/// smaller at the right and bottom edges
+pub fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size) {
+        for x in (0..width).step_by(tile_size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
@@ -0,0 +1,53 @@
+use rand::RngCore;
+use rand_pcg::Pcg32;
+
+/// Source of random numbers for a single camera sample. It is seeded from the render seed,
+/// the pixel and the sample index alone, so a pixel gets the same random sequence no matter
+/// which thread renders it, which keeps images bit-identical across thread counts.
+pub struct Sampler {
+    rng: Pcg32,
+}
+
+impl Sampler {
+    pub fn new(seed: u64, pixel: u64, sample: u64) -> Sampler {
+        let state = splitmix64(splitmix64(splitmix64(seed) ^ pixel) ^ sample);
+        Sampler {
+            rng: Pcg32::new(state, pixel),
+        }
+    }
+
+    /// Source of random numbers for decisions taken while intersecting a ray, where the
+    /// sampler of the camera sample is not at hand. It is seeded from the `medium_seed` the
+    /// integrator drew for the ray and from `salt`, which tells apart the media along it.
+    pub fn for_medium(seed: u64, salt: f64) -> Sampler {
+        Sampler {
+            rng: Pcg32::new(splitmix64(splitmix64(seed) ^ salt.to_bits()), 0),
+        }
+    }
+}
+
+/// Finalizer of the SplitMix64 generator, scrambles the bits of nearby inputs
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
@@ -0,0 +1,1340 @@
+//! Scene description files. Every non-empty line is one statement made of a keyword,
+//! optional positional arguments and `key=value` fields, `#` starts a comment:
+//!
+//! ```text
+//! render width=1200 aspect_ratio=1.5 samples=500 max_depth=50 bvh=sah adaptive=0.01 min_samples=16 roulette_depth=3 diffuse_depth=4 specular_depth=8 transmission_depth=12
+//! camera lookfrom=13,2,3 lookat=0,0,0 vup=0,1,0 vfov=20 aperture=0.1 focus_dist=10 shutter_open=0 shutter_close=1
+//! texture checks checker even=0.2,0.3,0.1 odd=0.9,0.9,0.9 scale=0.5
+//! texture earth image file=earthmap.jpg
+//! texture stone noise kind=marble color=1,1,1 scale=4 seed=7
+//! material ground lambertian texture=checks
+//! material gold metal albedo=0.8,0.6,0.2 fuzz=0.1
+//! material glass dielectric ir=1.5
+//! material copper conductor eta=0.27,0.68,1.22 k=3.61,2.62,2.29 roughness=0.3
+//! material frosted rough_dielectric ir=1.5 roughness=0.2
+//! material paint principled albedo=0.8,0.1,0.1 metallic=0 roughness=0.4 clearcoat=1
+//! material velvet principled texture=checks sheen=1 roughness_texture=stone
+//! material lamp diffuse_light color=1,0.9,0.8 intensity=4
+//! material smoke isotropic albedo=0.9,0.9,0.9
+//! environment map file=sky.hdr rotate=90 intensity=1.5
+//! sphere center=0,-1000,0 radius=1000 material=ground
+//! moving_sphere center0=0,1,0 center1=0,1.5,0 time0=0 time1=1 radius=0.5 material=gold
+//! xz_rect x0=-1 x1=1 z0=-1 z1=1 k=5 flip=true material=lamp
+//! box min=-1,0,-1 max=1,2,1 material=ground
+//! plane point=0,0,0 normal=0,1,0 material=ground
+//! medium box min=-5,0,-5 max=5,1,5 density=0.2 material=smoke
+//! triangle v0=0,0,0 v1=1,0,0 v2=0,1,0 uv0=0,0 uv1=1,0 uv2=0,1 material=gold
+//! mesh file=teapot.obj scale=2,2,2 rotate=0,45,0 translate=0,1,0
+//! ```
+//!
+//! Paths end after `max_depth` rays, after as many bounces of one kind as its `*_depth`
+//! field allows, or by Russian roulette once they bounced `roulette_depth` times.
+//! Every object takes the optional `scale`, `rotate` and `translate` fields to place it.
+//! All transformed meshes from the same file share one copy of the geometry.
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::box_shape::BoxShape;
+use crate::bvh::{BvhNode, SplitMethod};
+use crate::camera::Camera;
+use crate::constant_medium::ConstantMedium;
+use crate::environment::{Environment, EnvironmentMap};
+use crate::hittable::{Flipped, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::{
+    Conductor, Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal, Principled,
+    RoughDielectric,
+};
+use crate::mesh::MeshLight;
+use crate::moving_sphere::MovingSphere;
+use crate::obj::{self, ObjError};
+use crate::perlin::Perlin;
+use crate::plane::Plane;
+use crate::sphere::Sphere;
+use crate::texture::{Checker, ImageTexture, NoiseKind, NoiseTexture, Texture};
+use crate::transform::{Transform, Transformed};
+use crate::triangle::{Triangle, Uv};
+use crate::vec3::{Color, Point3, Vec3};
+use image::ImageError;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub image_width: i32,
+    pub image_height: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub split_method: SplitMethod,
+    /// Seeds every pixel sample, equal seeds give bit-identical images
+    pub seed: u64,
+    /// Stop sampling a pixel once the standard error of its mean luminance falls below this
+    /// fraction of the mean, `None` gives every pixel `samples_per_pixel` samples
+    pub adaptive_threshold: Option<f64>,
+    /// Samples a pixel gets before adaptive sampling may stop it
+    pub min_samples_per_pixel: i32,
+    /// Bounces a path takes before Russian roulette may end it
+    pub roulette_depth: i32,
+    /// Most diffuse bounces in a path, `None` leaves it to `max_depth`
+    pub diffuse_depth: Option<i32>,
+    /// Most specular and glossy reflections in a path, `None` leaves it to `max_depth`
+    pub specular_depth: Option<i32>,
+    /// Most transmissions through surfaces in a path, `None` leaves it to `max_depth`
+    pub transmission_depth: Option<i32>,
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+
+    /// Changes the image size, a missing dimension is derived from the current aspect ratio
+    pub fn resize(&mut self, width: Option<i32>, height: Option<i32>) {
+        let aspect_ratio = self.aspect_ratio();
+        match (width, height) {
+            (Some(width), Some(height)) => {
+                self.image_width = width;
+                self.image_height = height;
+            }
+            (Some(width), None) => {
+                self.image_width = width;
+                self.image_height = ((width as f64 / aspect_ratio) as i32).max(1);
+            }
+            (None, Some(height)) => {
+                self.image_width = ((height as f64 * aspect_ratio) as i32).max(1);
+                self.image_height = height;
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        const ASPECT_RATIO: f64 = 3.0 / 2.0;
+        const IMAGE_WIDTH: i32 = 1200;
+
+        RenderSettings {
+            image_width: IMAGE_WIDTH,
+            image_height: (IMAGE_WIDTH as f64 / ASPECT_RATIO) as i32,
+            samples_per_pixel: 500,
+            max_depth: 500,
+            split_method: SplitMethod::Sah,
+            seed: 0,
+            adaptive_threshold: None,
+            min_samples_per_pixel: 16,
+            roulette_depth: 3,
+            diffuse_depth: None,
+            specular_depth: None,
+            transmission_depth: None,
+        }
+    }
+}
+
+/// Parameters of `Camera::new` except for the aspect ratio, which follows the image size
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+pub struct Scene {
+    /// A BVH over all bounded objects, followed by the unbounded ones
+    pub world: HittableList,
+    /// Emitters that are sampled explicitly, they are part of `world` as well
+    pub lights: HittableList,
+    pub environment: Environment,
+    pub camera: CameraSettings,
+    pub settings: RenderSettings,
+    /// Files the scene file refers to, meshes, their material libraries and images, in the
+    /// order they were first read
+    pub files: Vec<PathBuf>,
+}
+
+impl Scene {
+    pub fn camera(&self) -> Camera {
+        Camera::new(
+            self.camera.lookfrom,
+            self.camera.lookat,
+            self.camera.vup,
+            self.camera.vfov,
+            self.settings.aspect_ratio(),
+            self.camera.aperture,
+            self.camera.focus_dist,
+        )
+        .with_shutter(self.camera.shutter_open, self.camera.shutter_close)
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Mesh(ObjError),
+    Texture {
+        path: PathBuf,
+        source: ImageError,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            SceneError::Mesh(err) => write!(f, "{}", err),
+            SceneError::Texture { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { .. } => None,
+            SceneError::Mesh(err) => Some(err),
+            SceneError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(err: ObjError) -> Self {
+        SceneError::Mesh(err)
+    }
+}
+
+/// Values that can appear on the right-hand side of a field
+trait FieldValue: Sized {
+    const DESCRIPTION: &'static str;
+
+    fn parse_value(value: &str) -> Option<Self>;
+}
+
+impl FieldValue for f64 {
+    const DESCRIPTION: &'static str = "a number";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        f64::from_str(value).ok().filter(|v| v.is_finite())
+    }
+}
+
+impl FieldValue for i32 {
+    const DESCRIPTION: &'static str = "an integer";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        i32::from_str(value).ok()
+    }
+}
+
+impl FieldValue for Vec3 {
+    const DESCRIPTION: &'static str = "three comma separated numbers";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        let mut components = value.split(',').map(f64::parse_value);
+        let v = Vec3::new(
+            components.next()??,
+            components.next()??,
+            components.next()??,
+        );
+        components.next().is_none().then_some(v)
+    }
+}
+
+impl FieldValue for Uv {
+    const DESCRIPTION: &'static str = "two comma separated numbers";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        let (u, v) = value.split_once(',')?;
+        Some((f64::parse_value(u)?, f64::parse_value(v)?))
+    }
+}
+
+impl FieldValue for String {
+    const DESCRIPTION: &'static str = "a name";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+impl FieldValue for SplitMethod {
+    const DESCRIPTION: &'static str = "'sah' or 'median'";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        match value {
+            "sah" => Some(SplitMethod::Sah),
+            "median" => Some(SplitMethod::Median),
+            _ => None,
+        }
+    }
+}
+
+impl FieldValue for bool {
+    const DESCRIPTION: &'static str = "'true' or 'false'";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        bool::from_str(value).ok()
+    }
+}
+
+impl FieldValue for u64 {
+    const DESCRIPTION: &'static str = "a non-negative integer";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        u64::from_str(value).ok()
+    }
+}
+
+impl FieldValue for NoiseKind {
+    const DESCRIPTION: &'static str = "'smooth', 'turbulence' or 'marble'";
+
+    fn parse_value(value: &str) -> Option<Self> {
+        match value {
+            "smooth" => Some(NoiseKind::Smooth),
+            "turbulence" => Some(NoiseKind::Turbulence),
+            "marble" => Some(NoiseKind::Marble),
+            _ => None,
+        }
+    }
+}
+
+/// One parsed line of the scene file
+struct Statement<'a> {
+    path: &'a Path,
+    line: usize,
+    keyword: &'a str,
+    positional: Vec<&'a str>,
+    fields: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Statement<'a> {
+    fn parse(path: &'a Path, line: usize, text: &'a str) -> Result<Option<Self>, SceneError> {
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(None),
+        };
+
+        let mut statement = Statement {
+            path,
+            line,
+            keyword,
+            positional: Vec::new(),
+            fields: HashMap::new(),
+        };
+
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    if key.is_empty() || value.is_empty() {
+                        return Err(statement.error(format!("malformed field '{}'", token)));
+                    }
+                    if statement.fields.insert(key, value).is_some() {
+                        return Err(statement.error(format!("field '{}' given twice", key)));
+                    }
+                }
+                None if statement.fields.is_empty() => statement.positional.push(token),
+                None => {
+                    return Err(statement.error(format!(
+                        "unexpected '{}' after the fields of '{}'",
+                        token, keyword
+                    )))
+                }
+            }
+        }
+
+        Ok(Some(statement))
+    }
+
+    fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn optional<T: FieldValue>(&mut self, key: &str) -> Result<Option<T>, SceneError> {
+        match self.fields.remove(key) {
+            Some(value) => T::parse_value(value).map(Some).ok_or_else(|| {
+                self.error(format!(
+                    "'{}' of '{}' must be {}, found '{}'",
+                    key,
+                    self.keyword,
+                    T::DESCRIPTION,
+                    value
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn required<T: FieldValue>(&mut self, key: &str) -> Result<T, SceneError> {
+        self.optional(key)?
+            .ok_or_else(|| self.error(format!("'{}' is missing field '{}'", self.keyword, key)))
+    }
+
+    fn check_range<T: fmt::Display>(
+        &self,
+        key: &str,
+        value: T,
+        valid: bool,
+        expected: &str,
+    ) -> Result<T, SceneError> {
+        if valid {
+            Ok(value)
+        } else {
+            Err(self.error(format!(
+                "'{}' of '{}' must be {}, found {}",
+                key, self.keyword, expected, value
+            )))
+        }
+    }
+
+    fn positive<T: FieldValue + PartialOrd + Default + fmt::Display + Copy>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<T>, SceneError> {
+        match self.optional::<T>(key)? {
+            Some(value) => self
+                .check_range(key, value, value > T::default(), "positive")
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn non_negative<T: FieldValue + PartialOrd + Default + fmt::Display + Copy>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<T>, SceneError> {
+        match self.optional::<T>(key)? {
+            Some(value) => self
+                .check_range(key, value, value >= T::default(), "non-negative")
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Fails on any field that has not been consumed
+    fn finish(self) -> Result<(), SceneError> {
+        let mut unknown: Vec<&str> = self.fields.keys().copied().collect();
+        unknown.sort_unstable();
+        match unknown.first() {
+            Some(key) => Err(self.error(format!("unknown field '{}' for '{}'", key, self.keyword))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SceneBuilder {
+    settings: RenderSettings,
+    camera: Option<CameraSettings>,
+    environment: Environment,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    world: HittableList,
+    lights: HittableList,
+    /// Meshes loaded for instancing and their emissive parts, by path
+    meshes: HashMap<PathBuf, (Arc<BvhNode>, Vec<Arc<MeshLight>>)>,
+    /// Files read so far, some maybe more than once
+    files: Vec<PathBuf>,
+}
+
+impl SceneBuilder {
+    fn statement(&mut self, mut statement: Statement, directory: &Path) -> Result<(), SceneError> {
+        // Only textures, materials, the environment and media take positional arguments, their
+        // name, type or shape
+        if let Some(token) = statement.positional.first() {
+            let keywords = ["texture", "material", "environment", "medium"];
+            if !keywords.contains(&statement.keyword) {
+                return Err(statement.error(format!(
+                    "unexpected '{}', fields are written as key=value",
+                    token
+                )));
+            }
+        }
+
+        match statement.keyword {
+            "render" => self.render(&mut statement)?,
+            "camera" => self.camera(&mut statement)?,
+            "texture" => self.texture(&mut statement, directory)?,
+            "material" => self.material(&mut statement)?,
+            "environment" => self.environment(&mut statement, directory)?,
+            "sphere" => {
+                let center = statement.required("center")?;
+                let radius: f64 = statement.required("radius")?;
+                statement.check_range("radius", radius, radius != 0.0, "non-zero")?;
+                let material = self.lookup_material(&mut statement)?;
+                let sphere = Sphere::new(center, radius, material.clone());
+                self.add_object(&mut statement, sphere, material.is_emissive())?;
+            }
+            "moving_sphere" => {
+                let center0 = statement.required("center0")?;
+                let center1 = statement.required("center1")?;
+                let time0: f64 = statement.optional("time0")?.unwrap_or(0.0);
+                let time1: f64 = statement.optional("time1")?.unwrap_or(1.0);
+                statement.check_range("time1", time1, time1 >= time0, "at least 'time0'")?;
+                let radius: f64 = statement.required("radius")?;
+                statement.check_range("radius", radius, radius != 0.0, "non-zero")?;
+                let material = self.lookup_material(&mut statement)?;
+                let sphere =
+                    MovingSphere::new((center0, time0), (center1, time1), radius, material);
+                // Light sampling needs a fixed position, so moving emitters are only found
+                // by the paths that happen to hit them
+                self.add_object(&mut statement, sphere, false)?;
+            }
+            "xy_rect" => {
+                let x = SceneBuilder::interval(&mut statement, "x0", "x1")?;
+                let y = SceneBuilder::interval(&mut statement, "y0", "y1")?;
+                let k = statement.required("k")?;
+                let material = self.lookup_material(&mut statement)?;
+                self.add_rect(
+                    &mut statement,
+                    XyRect::new(x, y, k, material.clone()),
+                    &material,
+                )?;
+            }
+            "xz_rect" => {
+                let x = SceneBuilder::interval(&mut statement, "x0", "x1")?;
+                let z = SceneBuilder::interval(&mut statement, "z0", "z1")?;
+                let k = statement.required("k")?;
+                let material = self.lookup_material(&mut statement)?;
+                self.add_rect(
+                    &mut statement,
+                    XzRect::new(x, z, k, material.clone()),
+                    &material,
+                )?;
+            }
+            "yz_rect" => {
+                let y = SceneBuilder::interval(&mut statement, "y0", "y1")?;
+                let z = SceneBuilder::interval(&mut statement, "z0", "z1")?;
+                let k = statement.required("k")?;
+                let material = self.lookup_material(&mut statement)?;
+                self.add_rect(
+                    &mut statement,
+                    YzRect::new(y, z, k, material.clone()),
+                    &material,
+                )?;
+            }
+            "box" => {
+                let minimum: Point3 = statement.required("min")?;
+                let maximum: Point3 = statement.required("max")?;
+                let valid = minimum.x < maximum.x && minimum.y < maximum.y && minimum.z < maximum.z;
+                statement.check_range("max", maximum, valid, "greater than 'min' per axis")?;
+                let material = self.lookup_material(&mut statement)?;
+                let shape = BoxShape::new(minimum, maximum, material.clone());
+                self.add_object(&mut statement, shape, material.is_emissive())?;
+            }
+            "plane" => {
+                let point = statement.required("point")?;
+                let normal: Vec3 = statement.required("normal")?;
+                statement.check_range("normal", normal, !normal.near_zero(), "non-zero")?;
+                let material = self.lookup_material(&mut statement)?;
+                // Infinite planes cannot be sampled as lights
+                self.add_object(&mut statement, Plane::new(point, normal, material), false)?;
+            }
+            "triangle" => {
+                let v0 = statement.required("v0")?;
+                let v1 = statement.required("v1")?;
+                let v2 = statement.required("v2")?;
+                let material = self.lookup_material(&mut statement)?;
+                let mut triangle = Triangle::new(v0, v1, v2, material.clone());
+                if let Some(normals) = SceneBuilder::corners(&mut statement, "n")? {
+                    triangle = triangle.with_normals(normals);
+                }
+                if let Some(uvs) = SceneBuilder::corners(&mut statement, "uv")? {
+                    triangle = triangle.with_uvs(uvs);
+                }
+                self.add_object(&mut statement, triangle, material.is_emissive())?;
+            }
+            "medium" => self.medium(&mut statement)?,
+            "mesh" => {
+                let path = directory.join(statement.required::<String>("file")?);
+                self.files.push(path.clone());
+                match SceneBuilder::transform(&mut statement)? {
+                    // Every transformed instance of a file shares one copy of its geometry
+                    Some(transform) => {
+                        let (mesh, lights) = match self.meshes.get(&path) {
+                            Some(mesh) => mesh.clone(),
+                            None => {
+                                let (objects, lights) = self.load_mesh(&path)?;
+                                let mesh = Arc::new(BvhNode::from_primitives(
+                                    objects.into_objects(),
+                                    self.settings.split_method,
+                                ));
+                                let lights: Vec<_> = lights.into_iter().map(Arc::new).collect();
+                                self.meshes.insert(path, (mesh.clone(), lights.clone()));
+                                (mesh, lights)
+                            }
+                        };
+                        self.world.add(Transformed::new(mesh, transform));
+                        for light in lights {
+                            self.lights.add(Transformed::new(light, transform));
+                        }
+                    }
+                    None => {
+                        let (objects, lights) = self.load_mesh(&path)?;
+                        self.world.append(objects);
+                        for light in lights {
+                            self.lights.add(light);
+                        }
+                    }
+                }
+            }
+            keyword => return Err(statement.error(format!("unknown statement '{}'", keyword))),
+        }
+
+        statement.finish()
+    }
+
+    /// A volume of constant density filling a sphere or a box, scattering with its material
+    fn medium(&mut self, statement: &mut Statement) -> Result<(), SceneError> {
+        let density: f64 = statement.required("density")?;
+        statement.check_range("density", density, density > 0.0, "positive")?;
+        let material = self.lookup_material(statement)?;
+        match statement.positional.as_slice() {
+            ["sphere"] => {
+                let center = statement.required("center")?;
+                let radius: f64 = statement.required("radius")?;
+                statement.check_range("radius", radius, radius > 0.0, "positive")?;
+                let boundary = Sphere::new(center, radius, Lambertian::new(Color::default()));
+                let medium = ConstantMedium::new(boundary, density, material);
+                self.add_object(statement, medium, false)
+            }
+            ["box"] => {
+                let minimum: Point3 = statement.required("min")?;
+                let maximum: Point3 = statement.required("max")?;
+                let valid = minimum.x < maximum.x && minimum.y < maximum.y && minimum.z < maximum.z;
+                statement.check_range("max", maximum, valid, "greater than 'min' per axis")?;
+                let boundary = BoxShape::new(minimum, maximum, Lambertian::new(Color::default()));
+                let medium = ConstantMedium::new(boundary, density, material);
+                self.add_object(statement, medium, false)
+            }
+            _ => Err(statement.error("expected 'medium sphere [fields]' or 'medium box [fields]'")),
+        }
+    }
+
+    /// The meshes of an OBJ file, and the emissive ones again to sample as lights
+    fn load_mesh(&mut self, path: &Path) -> Result<(HittableList, Vec<MeshLight>), SceneError> {
+        let mut objects = HittableList::new();
+        let mut lights = Vec::new();
+        for mesh in obj::load_meshes(path, &mut self.files)? {
+            if mesh.is_emissive() {
+                lights.extend(mesh.light(SplitMethod::Sah));
+            }
+            objects.add(mesh.into_bvh(SplitMethod::Sah));
+        }
+        Ok((objects, lights))
+    }
+
+    /// Places an object with the statement's transform, if it has one, and adds it to the world
+    fn add_object(
+        &mut self,
+        statement: &mut Statement,
+        object: impl Hittable + Send + Sync + 'static,
+        sample_as_light: bool,
+    ) -> Result<(), SceneError> {
+        match SceneBuilder::transform(statement)? {
+            Some(transform) => self.register(Transformed::new(object, transform), sample_as_light),
+            None => self.register(object, sample_as_light),
+        }
+        Ok(())
+    }
+
+    /// Adds an object to the world, and to the lights too if requested
+    fn register(&mut self, object: impl Hittable + Send + Sync + 'static, sample_as_light: bool) {
+        if sample_as_light {
+            let object = Arc::new(object);
+            self.world.add(object.clone());
+            self.lights.add(object);
+        } else {
+            self.world.add(object);
+        }
+    }
+
+    /// Rectangles face the positive direction of their constant axis unless `flip` is set
+    fn add_rect(
+        &mut self,
+        statement: &mut Statement,
+        rect: impl Hittable + Send + Sync + 'static,
+        material: &Arc<dyn Material>,
+    ) -> Result<(), SceneError> {
+        if statement.optional("flip")?.unwrap_or(false) {
+            self.add_object(statement, Flipped(rect), material.is_emissive())
+        } else {
+            self.add_object(statement, rect, material.is_emissive())
+        }
+    }
+
+    fn render(&mut self, statement: &mut Statement) -> Result<(), SceneError> {
+        let settings = &mut self.settings;
+        if let Some(width) = statement.positive("width")? {
+            settings.image_width = width;
+        }
+
+        let height = statement.positive::<i32>("height")?;
+        let aspect_ratio = statement.positive::<f64>("aspect_ratio")?;
+        settings.image_height = match (height, aspect_ratio) {
+            (Some(_), Some(_)) => {
+                return Err(statement.error("give either 'height' or 'aspect_ratio', not both"))
+            }
+            (Some(height), None) => height,
+            (None, Some(ratio)) => (settings.image_width as f64 / ratio) as i32,
+            (None, None) => {
+                (settings.image_width as f64 / RenderSettings::default().aspect_ratio()) as i32
+            }
+        };
+        if settings.image_height < 1 {
+            return Err(statement.error("the image must be at least one pixel high"));
+        }
+        if settings
+            .image_width
+            .checked_mul(settings.image_height)
+            .is_none()
+        {
+            return Err(statement.error("the image has too many pixels"));
+        }
+
+        if let Some(samples) = statement.positive("samples")? {
+            settings.samples_per_pixel = samples;
+        }
+        if let Some(max_depth) = statement.positive("max_depth")? {
+            settings.max_depth = max_depth;
+        }
+        if let Some(split_method) = statement.optional("bvh")? {
+            settings.split_method = split_method;
+        }
+        if let Some(threshold) = statement.positive("adaptive")? {
+            settings.adaptive_threshold = Some(threshold);
+        }
+        if let Some(min_samples) = statement.positive("min_samples")? {
+            settings.min_samples_per_pixel = min_samples;
+        }
+        if let Some(roulette_depth) = statement.non_negative("roulette_depth")? {
+            settings.roulette_depth = roulette_depth;
+        }
+        if let Some(depth) = statement.non_negative("diffuse_depth")? {
+            settings.diffuse_depth = Some(depth);
+        }
+        if let Some(depth) = statement.non_negative("specular_depth")? {
+            settings.specular_depth = Some(depth);
+        }
+        if let Some(depth) = statement.non_negative("transmission_depth")? {
+            settings.transmission_depth = Some(depth);
+        }
+
+        Ok(())
+    }
+
+    fn camera(&mut self, statement: &mut Statement) -> Result<(), SceneError> {
+        if self.camera.is_some() {
+            return Err(statement.error("the camera is already defined"));
+        }
+
+        let lookfrom: Point3 = statement.required("lookfrom")?;
+        let lookat: Point3 = statement.required("lookat")?;
+        if (lookfrom - lookat).near_zero() {
+            return Err(statement.error("'lookfrom' and 'lookat' must differ"));
+        }
+
+        let vup = statement
+            .optional("vup")?
+            .unwrap_or_else(|| Vec3::new(0.0, 1.0, 0.0));
+        let vfov: f64 = statement.required("vfov")?;
+        statement.check_range("vfov", vfov, vfov > 0.0 && vfov < 180.0, "in (0, 180)")?;
+        let aperture: f64 = statement.optional("aperture")?.unwrap_or(0.0);
+        statement.check_range("aperture", aperture, aperture >= 0.0, "non-negative")?;
+        let focus_dist = statement
+            .positive("focus_dist")?
+            .unwrap_or_else(|| (lookfrom - lookat).length());
+        let shutter_open: f64 = statement.optional("shutter_open")?.unwrap_or(0.0);
+        let shutter_close: f64 = statement.optional("shutter_close")?.unwrap_or(shutter_open);
+        statement.check_range(
+            "shutter_close",
+            shutter_close,
+            shutter_close >= shutter_open,
+            "at least 'shutter_open'",
+        )?;
+
+        self.camera = Some(CameraSettings {
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aperture,
+            focus_dist,
+            shutter_open,
+            shutter_close,
+        });
+        Ok(())
+    }
+
+    fn texture(&mut self, statement: &mut Statement, directory: &Path) -> Result<(), SceneError> {
+        let (name, kind) = match statement.positional.as_slice() {
+            [name, kind] => (name.to_string(), *kind),
+            _ => return Err(statement.error("expected 'texture <name> <type> [fields]'")),
+        };
+        if self.textures.contains_key(&name) {
+            return Err(statement.error(format!("texture '{}' is already defined", name)));
+        }
+
+        let texture: Arc<dyn Texture> = match kind {
+            "checker" => {
+                let even = SceneBuilder::color(statement, "even")?;
+                let odd = SceneBuilder::color(statement, "odd")?;
+                let scale = statement.positive("scale")?.unwrap_or(1.0);
+                Arc::new(Checker::new(even, odd, scale))
+            }
+            "image" => {
+                let file: String = statement.required("file")?;
+                let path = directory.join(file);
+                self.files.push(path.clone());
+                match ImageTexture::open(&path) {
+                    Ok(image) => Arc::new(image),
+                    Err(source) => return Err(SceneError::Texture { path, source }),
+                }
+            }
+            "noise" => {
+                let noise_kind = statement.optional("kind")?.unwrap_or(NoiseKind::Smooth);
+                let color = if statement.fields.contains_key("color") {
+                    SceneBuilder::color(statement, "color")?
+                } else {
+                    Color::new(1.0, 1.0, 1.0)
+                };
+                let scale = statement.positive("scale")?.unwrap_or(1.0);
+                let seed = statement.optional("seed")?.unwrap_or(0);
+                let perlin = Perlin::new(&mut Pcg32::seed_from_u64(seed));
+                Arc::new(NoiseTexture::new(perlin, noise_kind, color, scale))
+            }
+            kind => {
+                return Err(statement.error(format!(
+                    "unknown texture type '{}', expected 'checker', 'image' or 'noise'",
+                    kind
+                )))
+            }
+        };
+
+        self.textures.insert(name, texture);
+        Ok(())
+    }
+
+    fn material(&mut self, statement: &mut Statement) -> Result<(), SceneError> {
+        let (name, kind) = match statement.positional.as_slice() {
+            [name, kind] => (name.to_string(), *kind),
+            _ => return Err(statement.error("expected 'material <name> <type> [fields]'")),
+        };
+        if self.materials.contains_key(&name) {
+            return Err(statement.error(format!("material '{}' is already defined", name)));
+        }
+
+        let material: Arc<dyn Material> = match kind {
+            "lambertian" => Arc::new(Lambertian::new(self.albedo(statement)?)),
+            "metal" => {
+                let albedo = self.albedo(statement)?;
+                let fuzz: f64 = statement.optional("fuzz")?.unwrap_or(0.0);
+                statement.check_range("fuzz", fuzz, (0.0..=1.0).contains(&fuzz), "in [0, 1]")?;
+                Arc::new(Metal::new(albedo, fuzz))
+            }
+            "dielectric" => {
+                let ir: f64 = statement.required("ir")?;
+                statement.check_range("ir", ir, ir > 0.0, "positive")?;
+                Arc::new(Dielectric::new(ir))
+            }
+            "diffuse_light" => {
+                let color: Vec3 = statement.required("color")?;
+                let valid = [color.x, color.y, color.z].iter().all(|c| *c >= 0.0);
+                statement.check_range("color", color, valid, "non-negative per channel")?;
+                let intensity = statement.positive("intensity")?.unwrap_or(1.0);
+                Arc::new(DiffuseLight::new(color, intensity))
+            }
+            "isotropic" => Arc::new(Isotropic::new(self.albedo(statement)?)),
+            "conductor" => {
+                let eta: Vec3 = statement.required("eta")?;
+                let valid = [eta.x, eta.y, eta.z].iter().all(|c| *c > 0.0);
+                statement.check_range("eta", eta, valid, "positive per channel")?;
+                let k: Vec3 = statement.required("k")?;
+                let valid = [k.x, k.y, k.z].iter().all(|c| *c >= 0.0);
+                statement.check_range("k", k, valid, "non-negative per channel")?;
+                let roughness = SceneBuilder::roughness(statement)?;
+                Arc::new(Conductor::new(eta, k, roughness))
+            }
+            "rough_dielectric" => {
+                let ir: f64 = statement.required("ir")?;
+                statement.check_range("ir", ir, ir > 0.0, "positive")?;
+                let roughness = SceneBuilder::roughness(statement)?;
+                Arc::new(RoughDielectric::new(ir, roughness))
+            }
+            "principled" => {
+                let mut principled = Principled::new(self.albedo(statement)?);
+                if let Some(metallic) = self.parameter(statement, "metallic")? {
+                    principled = principled.with_metallic(metallic);
+                }
+                if let Some(roughness) = self.parameter(statement, "roughness")? {
+                    principled = principled.with_roughness(roughness);
+                }
+                if let Some(specular) = self.parameter(statement, "specular")? {
+                    principled = principled.with_specular(specular);
+                }
+                if let Some(clearcoat) = self.parameter(statement, "clearcoat")? {
+                    principled = principled.with_clearcoat(clearcoat);
+                }
+                if let Some(sheen) = self.parameter(statement, "sheen")? {
+                    principled = principled.with_sheen(sheen);
+                }
+                if let Some(transmission) = self.parameter(statement, "transmission")? {
+                    principled = principled.with_transmission(transmission);
+                }
+                Arc::new(principled)
+            }
+            kind => {
+                return Err(statement.error(format!(
+                    "unknown material type '{}', expected 'lambertian', 'metal', 'dielectric', \
+                     'diffuse_light', 'isotropic', 'conductor', 'rough_dielectric' or \
+                     'principled'",
+                    kind
+                )))
+            }
+        };
+
+        self.materials.insert(name, material);
+        Ok(())
+    }
+
+    fn environment(
+        &mut self,
+        statement: &mut Statement,
+        directory: &Path,
+    ) -> Result<(), SceneError> {
+        self.environment = match statement.positional.as_slice() {
+            ["sky"] => Environment::Sky,
+            ["solid"] => {
+                let color: Vec3 = statement.required("color")?;
+                let valid = [color.x, color.y, color.z].iter().all(|c| *c >= 0.0);
+                statement.check_range("color", color, valid, "non-negative per channel")?;
+                Environment::Solid(color)
+            }
+            ["map"] => {
+                let file: String = statement.required("file")?;
+                let rotation = statement.optional("rotate")?.unwrap_or(0.0);
+                let intensity = statement.positive("intensity")?.unwrap_or(1.0);
+                let path = directory.join(file);
+                self.files.push(path.clone());
+                match EnvironmentMap::open(&path, rotation, intensity) {
+                    Ok(map) => Environment::Map(Arc::new(map)),
+                    Err(source) => return Err(SceneError::Texture { path, source }),
+                }
+            }
+            _ => {
+                return Err(statement.error(
+                    "expected 'environment sky', 'environment solid color=r,g,b' or \
+                     'environment map file=<path>'",
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Either a constant `albedo` color or the name of a `texture`
+    fn albedo(&self, statement: &mut Statement) -> Result<Arc<dyn Texture>, SceneError> {
+        let texture: Option<String> = statement.optional("texture")?;
+        match (texture, statement.fields.contains_key("albedo")) {
+            (Some(_), true) => Err(statement.error("give either 'albedo' or 'texture', not both")),
+            (Some(name), false) => self
+                .textures
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| statement.error(format!("unknown texture '{}'", name))),
+            (None, _) => Ok(Arc::new(SceneBuilder::color(statement, "albedo")?)),
+        }
+    }
+
+    /// Optional parameter in [0, 1], either a constant given as `key` or the name of a
+    /// texture given as `<key>_texture`
+    fn parameter(
+        &self,
+        statement: &mut Statement,
+        key: &str,
+    ) -> Result<Option<Arc<dyn Texture>>, SceneError> {
+        let texture_key = format!("{}_texture", key);
+        let texture: Option<String> = statement.optional(&texture_key)?;
+        let value: Option<f64> = statement.optional(key)?;
+        match (texture, value) {
+            (Some(_), Some(_)) => Err(statement.error(format!(
+                "give either '{}' or '{}', not both",
+                key, texture_key
+            ))),
+            (Some(name), None) => match self.textures.get(&name) {
+                Some(texture) => Ok(Some(texture.clone())),
+                None => Err(statement.error(format!("unknown texture '{}'", name))),
+            },
+            (None, Some(value)) => {
+                statement.check_range(key, value, (0.0..=1.0).contains(&value), "in [0, 1]")?;
+                Ok(Some(Arc::new(Color::new(value, value, value))))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn roughness(statement: &mut Statement) -> Result<f64, SceneError> {
+        let roughness: f64 = statement.optional("roughness")?.unwrap_or(0.0);
+        statement.check_range(
+            "roughness",
+            roughness,
+            (0.0..=1.0).contains(&roughness),
+            "in [0, 1]",
+        )
+    }
+
+    /// Reads a required reflectance color, every channel in [0, 1]
+    fn color(statement: &mut Statement, key: &str) -> Result<Color, SceneError> {
+        let color: Color = statement.required(key)?;
+        let valid = [color.x, color.y, color.z]
+            .iter()
+            .all(|c| (0.0..=1.0).contains(c));
+        statement.check_range(key, color, valid, "in [0, 1] per channel")
+    }
+
+    /// Reads an optional per-vertex attribute, which has to be given for all three corners
+    fn corners<T: FieldValue>(
+        statement: &mut Statement,
+        prefix: &str,
+    ) -> Result<Option<[T; 3]>, SceneError> {
+        let keys = [0, 1, 2].map(|i| format!("{}{}", prefix, i));
+        match (
+            statement.optional(&keys[0])?,
+            statement.optional(&keys[1])?,
+            statement.optional(&keys[2])?,
+        ) {
+            (Some(a), Some(b), Some(c)) => Ok(Some([a, b, c])),
+            (None, None, None) => Ok(None),
+            _ => Err(statement.error(format!(
+                "give all of '{}', '{}' and '{}' or none of them",
+                keys[0], keys[1], keys[2]
+            ))),
+        }
+    }
+
+    /// Reads the optional `scale`, `rotate` and `translate` fields. They are applied in that
+    /// order, `rotate` holds angles in degrees around the x, y and z axes, applied in turn.
+    fn transform(statement: &mut Statement) -> Result<Option<Transform>, SceneError> {
+        let scale: Option<Vec3> = statement.optional("scale")?;
+        let rotate: Option<Vec3> = statement.optional("rotate")?;
+        let translate: Option<Vec3> = statement.optional("translate")?;
+        if scale.is_none() && rotate.is_none() && translate.is_none() {
+            return Ok(None);
+        }
+
+        let mut transform = Transform::default();
+        if let Some(factors) = scale {
+            let valid = factors.x != 0.0 && factors.y != 0.0 && factors.z != 0.0;
+            statement.check_range("scale", factors, valid, "non-zero per axis")?;
+            transform = Transform::scale(factors);
+        }
+        if let Some(angles) = rotate {
+            transform = Transform::rotate(Vec3::new(0.0, 0.0, 1.0), angles.z)
+                * Transform::rotate(Vec3::new(0.0, 1.0, 0.0), angles.y)
+                * Transform::rotate(Vec3::new(1.0, 0.0, 0.0), angles.x)
+                * transform;
+        }
+        if let Some(offset) = translate {
+            transform = Transform::translate(offset) * transform;
+        }
+        Ok(Some(transform))
+    }
+
+    /// Reads the two ends of a non-empty interval
+    fn interval(
+        statement: &mut Statement,
+        start: &str,
+        end: &str,
+    ) -> Result<(f64, f64), SceneError> {
+        let a: f64 = statement.required(start)?;
+        let b: f64 = statement.required(end)?;
+        statement.check_range(end, b, a < b, &format!("greater than '{}'", start))?;
+        Ok((a, b))
+    }
+
+    fn lookup_material(&self, statement: &mut Statement) -> Result<Arc<dyn Material>, SceneError> {
+        let name: String = statement.required("material")?;
+        self.materials
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| statement.error(format!("unknown material '{}'", name)))
+    }
+}
+
+/// Puts the objects into a BVH, unbounded ones such as planes are tested next to the tree
+pub fn build_world(objects: HittableList, split_method: SplitMethod) -> HittableList {
+    let mut world = HittableList::new();
+    world.add(BvhNode::from_primitives(
+        objects.into_objects(),
+        split_method,
+    ));
+    world
+}
+
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_scene(path, &source)
+}
+
+/// Builds the scene described by `source`, the contents of the scene file at `path`
+fn parse_scene(path: &Path, source: &str) -> Result<Scene, SceneError> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut builder = SceneBuilder::default();
+    for (index, text) in source.lines().enumerate() {
+        if let Some(statement) = Statement::parse(path, index + 1, text)? {
+            builder.statement(statement, directory)?;
+        }
+    }
+
+    let end_of_file = |message: &str| SceneError::Parse {
+        path: path.to_path_buf(),
+        line: source.lines().count(),
+        message: message.to_string(),
+    };
+
+    let camera = builder
+        .camera
+        .ok_or_else(|| end_of_file("the scene has no 'camera'"))?;
+    if builder.world.is_empty() {
+        return Err(end_of_file("the scene has no objects"));
+    }
+
+    let mut files = Vec::new();
+    for file in builder.files {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+
+    let settings = builder.settings;
+    Ok(Scene {
+        world: build_world(builder.world, settings.split_method),
+        lights: builder.lights,
+        environment: builder.environment,
+        camera,
+        settings,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "camera lookfrom=0,0,5 lookat=0,0,0 vfov=40";
+
+    /// The message of the error in the scene `source` and the line it points at
+    fn error(source: &str) -> (usize, String) {
+        match parse_scene(Path::new("test.scene"), source) {
+            Err(SceneError::Parse { line, message, .. }) => (line, message),
+            Err(err) => panic!("not a parse error: {}", err),
+            Ok(_) => panic!("the scene loaded"),
+        }
+    }
+
+    #[test]
+    fn valid_scenes_load() {
+        let source = format!(
+            "# comment\n\n{}\nmaterial red lambertian albedo=0.8,0.1,0.1\n\
+             sphere center=0,0,0 radius=1 material=red # trailing comment\n",
+            CAMERA
+        );
+        let scene = parse_scene(Path::new("test.scene"), &source).unwrap();
+        assert_eq!(scene.camera.vfov, 40.0);
+    }
+
+    #[test]
+    fn unknown_keywords() {
+        let source = format!("{}\n\nsphear center=0,0,0 radius=1\n", CAMERA);
+        assert_eq!(
+            error(&source),
+            (3, "unknown statement 'sphear'".to_string())
+        );
+
+        let source = format!("{}\nbackground sky\n", CAMERA);
+        assert_eq!(
+            error(&source),
+            (
+                2,
+                "unexpected 'sky', fields are written as key=value".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        let source = format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\nsphere center=0,0,0 material=red\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (3, "'sphere' is missing field 'radius'".to_string())
+        );
+        assert_eq!(
+            error("camera lookfrom=0,0,5 vfov=40\n"),
+            (1, "'camera' is missing field 'lookat'".to_string())
+        );
+    }
+
+    #[test]
+    fn wrong_value_types() {
+        let source = format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\nsphere center=0,0,0 radius=big material=red\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (
+                3,
+                "'radius' of 'sphere' must be a number, found 'big'".to_string()
+            )
+        );
+        assert_eq!(
+            error("render samples=1.5\n"),
+            (
+                1,
+                "'samples' of 'render' must be an integer, found '1.5'".to_string()
+            )
+        );
+        assert_eq!(
+            error("render\ncamera lookfrom=0,0 lookat=0,0,0 vfov=40\n"),
+            (
+                2,
+                "'lookfrom' of 'camera' must be three comma separated numbers, found '0,0'"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            error("render samples=0\n"),
+            (
+                1,
+                "'samples' of 'render' must be positive, found 0".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn undefined_references() {
+        let source = format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\n\nsphere center=0,0,0 radius=1 material=blue\n",
+            CAMERA
+        );
+        assert_eq!(error(&source), (4, "unknown material 'blue'".to_string()));
+
+        let source = format!("{}\nmaterial wall lambertian texture=bricks\n", CAMERA);
+        assert_eq!(error(&source), (2, "unknown texture 'bricks'".to_string()));
+
+        let source = format!(
+            "{}\nmaterial red lambertian albedo=1,0,0\nmaterial red lambertian albedo=0,1,0\n",
+            CAMERA
+        );
+        assert_eq!(
+            error(&source),
+            (3, "material 'red' is already defined".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_fields() {
+        assert_eq!(
+            error("camera lookfrom= lookat=0,0,0\n"),
+            (1, "malformed field 'lookfrom='".to_string())
+        );
+        assert_eq!(
+            error("camera vfov=40 vfov=50\n"),
+            (1, "field 'vfov' given twice".to_string())
+        );
+        assert_eq!(
+            error(&format!("{} stray\n", CAMERA)),
+            (
+                1,
+                "unexpected 'stray' after the fields of 'camera'".to_string()
+            )
+        );
+        assert_eq!(
+            error(&format!("{} fov=40\n", CAMERA)),
+            (1, "unknown field 'fov' for 'camera'".to_string())
+        );
+    }
+
+    /// The format has no blocks, a scene that ends before it is complete is reported at
+    /// its last line
+    #[test]
+    fn incomplete_scenes() {
+        assert_eq!(
+            error(
+                "material red lambertian albedo=1,0,0\nsphere center=0,0,0 radius=1 material=red\n"
+            ),
+            (2, "the scene has no 'camera'".to_string())
+        );
+        assert_eq!(
+            error(&format!("render samples=4\n{}\n# nothing here\n", CAMERA)),
+            (3, "the scene has no objects".to_string())
+        );
+    }
+
+    #[test]
+    fn emissive_meshes_are_lights() {
+        let directory = std::env::temp_dir().join(format!("mesh-lights-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("lamp.obj"),
+            "mtllib lamp.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             usemtl lamp\nf 1 2 3\nusemtl wall\nf 3 2 1\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("lamp.mtl"),
+            "newmtl lamp\nKe 4 4 4\nnewmtl wall\nKd 0.5 0.5 0.5\n",
+        )
+        .unwrap();
+        let source = format!(
+            "{}\nmesh file=lamp.obj\nmesh file=lamp.obj translate=0,0,-1\n",
+            CAMERA
+        );
+        let scene = parse_scene(&directory.join("lamps.scene"), &source);
+        fs::remove_dir_all(&directory).unwrap();
+
+        // One light per instance, both in the way of a ray towards the lamp
+        let scene = scene.unwrap();
+        let origin = Point3::new(0.25, 0.25, 1.0);
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+        assert!(scene.lights.pdf_value(origin, direction) > 0.0);
+        assert_eq!(scene.lights.into_objects().len(), 2);
+    }
+}
@@ -1,9 +1,14 @@
+use crate::aabb::Aabb;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::{Point3, Vec3};
 use crate::{
     hittable::{HitRecord, Hittable},
     material::Material,
 };
+use rand::Rng;
+use std::f64::consts::PI;
 
 #[derive(Default)]
 pub struct Sphere {
@@ -23,32 +28,102 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.length_squared();
-        let half_b = oc.dot(ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return None;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            self.center,
+            self.radius,
+            self.material.as_deref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        let radius = Vec3::new(r, r, r);
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self
+            .hit(&Ray::new(origin, direction, 0.0), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
         }
-        let sqrtd = discriminant.sqrt();
 
-        // Find the nearest root that lies in the acceptable range
-        let mut root = -(sqrtd + half_b) / a;
-        if root < t_min || root > t_max {
-            root = (sqrtd - half_b) / a;
-            if root < t_min || root > t_max {
-                return None;
-            }
+        // Uniform over the cone of directions the sphere subtends
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 0.0;
         }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
-        let mut rec = HitRecord::new(ray.at(root), root, self.material.as_ref().map(Box::as_ref));
+        1.0 / solid_angle
+    }
 
-        let outward_normal: Vec3 = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(ray, outward_normal);
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).max(0.0).sqrt();
 
-        Some(rec)
+        let r1: f64 = sampler.gen();
+        let r2: f64 = sampler.gen();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        Onb::from_w(direction).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
     }
 }
+
+/// Intersection with a sphere at a given position, shared by the static and moving spheres
+pub fn hit_sphere<'a>(
+    center: Point3,
+    radius: f64,
+    material: Option<&'a dyn Material>,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    let oc = ray.origin - center;
+    let a = ray.direction.length_squared();
+    let half_b = oc.dot(ray.direction);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+
+    // Find the nearest root that lies in the acceptable range
+    let mut root = -(sqrtd + half_b) / a;
+    if root < t_min || root > t_max {
+        root = (sqrtd - half_b) / a;
+        if root < t_min || root > t_max {
+            return None;
+        }
+    }
+
+    let mut rec = HitRecord::new(ray.at(root), root, material);
+
+    let outward_normal: Vec3 = (rec.p - center) / radius;
+    rec.set_face_normal(ray, outward_normal);
+    (rec.u, rec.v) = uv(outward_normal);
+
+    Some(rec)
+}
+
+/// Texture coordinates of a point on the unit sphere, `u` goes around the y axis
+/// starting at -x and `v` goes from the bottom pole to the top one
+fn uv(p: Point3) -> (f64, f64) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+
+    (phi / (2.0 * PI), theta / PI)
+}
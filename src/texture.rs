@@ -0,0 +1,145 @@
+use crate::perlin::Perlin;
+use crate::vec3::{Color, Point3};
+use image::ImageError;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Color that varies over a surface, looked up by texture coordinates and hit point
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+// A plain color is a texture that is the same everywhere
+impl Texture for Color {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        *self
+    }
+}
+
+// Lets several materials share one texture
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.as_ref().value(u, v, p)
+    }
+}
+
+/// Solid 3D checkerboard of cubes with side `scale`
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(even: impl Texture + 'static, odd: impl Texture + 'static, scale: f64) -> Checker {
+        Checker {
+            even: Arc::new(even),
+            odd: Arc::new(odd),
+            scale,
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let cell =
+            (p.x / self.scale).floor() + (p.y / self.scale).floor() + (p.z / self.scale).floor();
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Image mapped over the texture coordinates, `v` runs from the bottom row up
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear colors, row by row from the top
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /// Loads a PNG or JPEG file
+    pub fn open(path: impl AsRef<Path>) -> Result<ImageTexture, ImageError> {
+        let image = image::open(path)?.into_rgb8();
+        let (width, height) = image.dimensions();
+
+        // Undo the gamma 2 that `to_display` applies on output
+        let to_linear = |channel: u8| {
+            let c = channel as f64 / 255.0;
+            c * c
+        };
+        let pixels = image
+            .pixels()
+            .map(|p| Color::new(to_linear(p[0]), to_linear(p[1]), to_linear(p[2])))
+            .collect();
+
+        Ok(ImageTexture {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        })
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        if self.pixels.is_empty() {
+            // Cyan makes a missing image stand out
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Smooth noise
+    Smooth,
+    /// Several octaves of noise, looks like clouds
+    Turbulence,
+    /// Turbulence bending stripes along z
+    Marble,
+}
+
+pub struct NoiseTexture {
+    perlin: Perlin,
+    kind: NoiseKind,
+    color: Color,
+    scale: f64,
+}
+
+impl NoiseTexture {
+    const OCTAVES: usize = 7;
+
+    pub fn new(perlin: Perlin, kind: NoiseKind, color: Color, scale: f64) -> NoiseTexture {
+        NoiseTexture {
+            perlin,
+            kind,
+            color,
+            scale,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let p = self.scale * p;
+        let intensity = match self.kind {
+            NoiseKind::Smooth => 0.5 * (1.0 + self.perlin.noise(p)),
+            NoiseKind::Turbulence => self.perlin.turbulence(p, NoiseTexture::OCTAVES),
+            NoiseKind::Marble => {
+                0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(p, NoiseTexture::OCTAVES)).sin())
+            }
+        };
+        intensity * self.color
+    }
+}
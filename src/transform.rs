@@ -0,0 +1,323 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use std::ops;
+
+type Matrix4 = [[f64; 4]; 4];
+
+const IDENTITY: Matrix4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Affine transform as a 4x4 matrix together with its inverse. Both are built side by side
+/// from the elementary transforms, so no general matrix inversion is ever needed.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+}
+
+impl Transform {
+    pub fn translate(offset: Vec3) -> Transform {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][3] = offset[axis];
+            inverse[axis][3] = -offset[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Scales along the axes, every factor has to be non-zero
+    pub fn scale(factors: Vec3) -> Transform {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][axis] = factors[axis];
+            inverse[axis][axis] = 1.0 / factors[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Counterclockwise rotation around `axis` when looking against it
+    pub fn rotate(axis: Vec3, degrees: f64) -> Transform {
+        let a = axis.normalize();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut matrix = IDENTITY;
+
+        // Rodrigues' rotation formula
+        let k = [[0.0, -a.z, a.y], [a.z, 0.0, -a.x], [-a.y, a.x, 0.0]];
+        for (i, row) in matrix.iter_mut().take(3).enumerate() {
+            for (j, entry) in row.iter_mut().take(3).enumerate() {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                *entry = identity * cos + sin * k[i][j] + (1.0 - cos) * a[i] * a[j];
+            }
+        }
+
+        // The inverse of a rotation is its transpose
+        Transform {
+            matrix,
+            inverse: transpose(&matrix),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        apply(&self.matrix, p, 1.0)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        apply(&self.matrix, v, 0.0)
+    }
+
+    /// Normals transform with the inverse transpose to stay perpendicular to the surface
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        apply(&transpose(&self.inverse), n, 0.0)
+    }
+
+    /// Determinant of the linear part, i.e. the factor volumes get scaled by
+    fn determinant(&self) -> f64 {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+/// `a * b` applies `b` first and `a` second
+impl ops::Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Self::Output {
+        Transform {
+            matrix: multiply(&self.matrix, &rhs.matrix),
+            inverse: multiply(&rhs.inverse, &self.inverse),
+        }
+    }
+}
+
+fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+fn transpose(m: &Matrix4) -> Matrix4 {
+    let mut transposed = [[0.0; 4]; 4];
+    for (i, row) in transposed.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = m[j][i];
+        }
+    }
+    transposed
+}
+
+/// Multiplies `(v, w)` by the matrix, `w` is 1 for points and 0 for directions
+fn apply(m: &Matrix4, v: Vec3, w: f64) -> Vec3 {
+    let row = |r: &[f64; 4]| r[0] * v.x + r[1] * v.y + r[2] * v.z + r[3] * w;
+    Vec3::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+/// Places an object, given in its own coordinates, into the world. Wrap the object in an
+/// `Arc` to instance the same geometry many times.
+pub struct Transformed<T> {
+    object: T,
+    transform: Transform,
+    bbox: Option<Aabb>,
+}
+
+impl<T: Hittable> Transformed<T> {
+    pub fn new(object: T, transform: Transform) -> Self {
+        // Box around the transformed corners of the object's box
+        let bbox = object.bounding_box().map(|b| {
+            let corners = (0..8).map(|i| {
+                transform.point(Point3::new(
+                    if i & 1 == 0 { b.minimum.x } else { b.maximum.x },
+                    if i & 2 == 0 { b.minimum.y } else { b.maximum.y },
+                    if i & 4 == 0 { b.minimum.z } else { b.maximum.z },
+                ))
+            });
+            corners
+                .map(|c| Aabb::new(c, c))
+                .reduce(Aabb::surrounding_box)
+                .unwrap()
+        });
+
+        Transformed {
+            object,
+            transform,
+            bbox,
+        }
+    }
+}
+
+impl<T: Hittable> Hittable for Transformed<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // The direction is not renormalized, so `t` is the same in both spaces
+        let to_object = self.transform.inverse();
+        let object_ray = Ray {
+            origin: to_object.point(ray.origin),
+            direction: to_object.vector(ray.direction),
+            ..*ray
+        };
+
+        let mut rec = self.object.hit(&object_ray, t_min, t_max)?;
+        rec.p = self.transform.point(rec.p);
+        // Which side the normal faces relative to the ray survives the transform
+        rec.normal = self.transform.normal(rec.normal).normalize();
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let to_object = self.transform.inverse();
+        let object_direction = to_object.vector(direction).normalize();
+        let pdf = self
+            .object
+            .pdf_value(to_object.point(origin), object_direction);
+
+        // Change of solid angle measure for the directions mapped by the linear part
+        let stretch = self.transform.vector(object_direction).length();
+        pdf * stretch.powi(3) / self.transform.determinant().abs()
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        let object_origin = self.transform.inverse().point(origin);
+        self.transform
+            .vector(self.object.random(object_origin, sampler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chi_square::assert_samples_match;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+    use rand::Rng;
+    use std::f64::consts::PI;
+
+    fn assert_close(what: &str, actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).length() < 1.0e-9,
+            "{} is {:?}, expected {:?}",
+            what,
+            actual,
+            expected
+        );
+    }
+
+    /// Scales unevenly, then rotates and moves away from the origin
+    fn placement() -> Transform {
+        Transform::translate(Vec3::new(1.0, -0.5, -4.0))
+            * Transform::rotate(Vec3::new(1.0, 2.0, 3.0), 40.0)
+            * Transform::scale(Vec3::new(2.0, 0.5, 1.0))
+    }
+
+    #[test]
+    fn inverses_undo_transforms() {
+        let transform = placement();
+        let product = multiply(&transform.matrix, &transform.inverse);
+        for (i, row) in product.iter().enumerate() {
+            for (j, entry) in row.iter().enumerate() {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                assert!((entry - identity).abs() < 1.0e-12, "{:?}", product);
+            }
+        }
+
+        let p = Point3::new(0.3, -1.2, 2.5);
+        assert_close("point", transform.inverse().point(transform.point(p)), p);
+        assert_close("vector", transform.inverse().vector(transform.vector(p)), p);
+        assert!((transform.determinant() - 1.0).abs() < 1.0e-12);
+        assert!((transform.inverse().determinant() - 1.0).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn hits_land_on_the_transformed_surface() {
+        // An ellipsoid x²/4 + y² + (z + 5)² = 1
+        let sphere = Sphere::new(Point3::default(), 1.0, Lambertian::new(Color::default()));
+        let transform = Transform::translate(Vec3::new(0.0, 0.0, -5.0))
+            * Transform::scale(Vec3::new(2.0, 1.0, 1.0));
+        let ellipsoid = Transformed::new(sphere, transform);
+
+        let bbox = ellipsoid.bounding_box().unwrap();
+        assert_close("box minimum", bbox.minimum, Vec3::new(-2.0, -1.0, -6.0));
+        assert_close("box maximum", bbox.maximum, Vec3::new(2.0, 1.0, -4.0));
+
+        let (sin, cos) = 60f64.to_radians().sin_cos();
+        let target = Point3::new(2.0 * cos, 0.0, -5.0 + sin);
+        let ray = Ray::new(Point3::default(), target, 0.0);
+        let rec = ellipsoid.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1.0e-9);
+        assert_close("point", rec.p, target);
+        // The gradient of the implicit surface, not the scaled normal of the sphere
+        let normal = Vec3::new(target.x / 4.0, target.y, target.z + 5.0).normalize();
+        assert_close("normal", rec.normal, normal);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let sphere = Sphere::new(Point3::default(), 1.0, Lambertian::new(Color::default()));
+        let ellipsoid = Transformed::new(sphere, placement());
+        let origin = Point3::default();
+
+        // Midpoint rule over cells of equal solid angle
+        let (cosines, angles) = (400, 800);
+        let cell = 4.0 * PI / (cosines * angles) as f64;
+        let mut integral = 0.0;
+        for i in 0..cosines {
+            let cos_theta = 1.0 - 2.0 * (i as f64 + 0.5) / cosines as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..angles {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / angles as f64;
+                let direction = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                integral += ellipsoid.pdf_value(origin, direction) * cell;
+            }
+        }
+        assert!(
+            (integral - 1.0).abs() < 0.01,
+            "the density integrates to {}",
+            integral
+        );
+
+        assert_samples_match(
+            "transformed sphere",
+            100_000,
+            |rng| {
+                let mut sampler = Sampler::new(rng.gen(), 0, 0);
+                Some(ellipsoid.random(origin, &mut sampler).normalize())
+            },
+            |direction| ellipsoid.pdf_value(origin, direction),
+        );
+    }
+}
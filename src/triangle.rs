@@ -0,0 +1,192 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use rand::Rng;
+
+pub type Uv = (f64, f64);
+
+// Pad flat boxes so that axis-aligned triangles still have some volume
+const BOX_PADDING: f64 = 1.0e-4;
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[Uv; 3]>,
+    material: Box<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: impl Material + 'static) -> Self {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            material: Box::new(material),
+        }
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [Uv; 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect(ray, &self.vertices, t_min, t_max)?;
+        let mut rec = HitRecord::new(ray.at(t), t, Some(self.material.as_ref()));
+        set_surface(
+            &mut rec,
+            ray,
+            &self.vertices,
+            self.normals.as_ref(),
+            self.uvs.as_ref(),
+            (b1, b2),
+        );
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(bounding_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        pdf_value(&self.vertices, origin, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut Sampler) -> Vec3 {
+        random_point(&self.vertices, sampler) - origin
+    }
+}
+
+/// Density in solid angle with which `random_point` picks `direction` from `origin`
+pub fn pdf_value(v: &[Point3; 3], origin: Point3, direction: Vec3) -> f64 {
+    match intersect(&Ray::new(origin, direction, 0.0), v, 0.001, f64::INFINITY) {
+        Some((t, _, _)) => area_density_to_solid_angle(normal(v), t, direction, area(v)),
+        None => 0.0,
+    }
+}
+
+/// Converts a uniform density over `area` to solid angle, for the point `t` along
+/// `direction` on a surface with `normal`, which need not be of unit length
+pub fn area_density_to_solid_angle(normal: Vec3, t: f64, direction: Vec3, area: f64) -> f64 {
+    let distance_squared = t * t * direction.length_squared();
+    let cosine = (direction.dot(normal) / (direction.length() * normal.length())).abs();
+    if cosine < 1.0e-8 {
+        return 0.0;
+    }
+
+    distance_squared / (cosine * area)
+}
+
+/// Uniform point on the triangle
+pub fn random_point(v: &[Point3; 3], sampler: &mut Sampler) -> Point3 {
+    let su = sampler.gen::<f64>().sqrt();
+    let b1 = 1.0 - su;
+    let b2 = sampler.gen::<f64>() * su;
+    v[0] + b1 * (v[1] - v[0]) + b2 * (v[2] - v[0])
+}
+
+/// Geometric normal, by the winding order and as long as twice the area
+pub fn normal(v: &[Point3; 3]) -> Vec3 {
+    (v[1] - v[0]).cross(v[2] - v[0])
+}
+
+pub fn area(v: &[Point3; 3]) -> f64 {
+    0.5 * normal(v).length()
+}
+
+/// Möller–Trumbore intersection, returns the ray parameter and the barycentrics of v1 and v2
+pub fn intersect(ray: &Ray, v: &[Point3; 3], t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    const EPSILON: f64 = 1.0e-12;
+
+    let edge1 = v[1] - v[0];
+    let edge2 = v[2] - v[0];
+    let pvec = ray.direction.cross(edge2);
+    let det = edge1.dot(pvec);
+
+    // Ray parallel to the triangle plane
+    if det.abs() < EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = ray.origin - v[0];
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let b2 = ray.direction.dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+
+    Some((t, b1, b2))
+}
+
+/// Fills in the normal and texture coordinates of a triangle hit
+pub fn set_surface(
+    rec: &mut HitRecord,
+    ray: &Ray,
+    v: &[Point3; 3],
+    normals: Option<&[Vec3; 3]>,
+    uvs: Option<&[Uv; 3]>,
+    (b1, b2): (f64, f64),
+) {
+    let b0 = 1.0 - b1 - b2;
+    let geometric_normal = normal(v).normalize();
+
+    match normals {
+        Some(n) => {
+            let shading_normal = (b0 * n[0] + b1 * n[1] + b2 * n[2]).normalize();
+
+            // Vertex normals decide which side is outside, regardless of the winding order
+            let outward_normal = if geometric_normal.dot(shading_normal) < 0.0 {
+                -geometric_normal
+            } else {
+                geometric_normal
+            };
+            rec.set_face_normal(ray, outward_normal);
+
+            rec.normal = if shading_normal.dot(rec.normal) < 0.0 {
+                -shading_normal
+            } else {
+                shading_normal
+            };
+        }
+        None => rec.set_face_normal(ray, geometric_normal),
+    }
+
+    let (u, v) = match uvs {
+        Some(uv) => (
+            b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0,
+            b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1,
+        ),
+        None => (b1, b2),
+    };
+    rec.u = u;
+    rec.v = v;
+}
+
+pub fn bounding_box(v: &[Point3; 3]) -> Aabb {
+    let padding = Vec3::new(BOX_PADDING, BOX_PADDING, BOX_PADDING);
+    let bbox = Aabb::surrounding_box(Aabb::new(v[0], v[0]), Aabb::new(v[1], v[1]));
+    let bbox = Aabb::surrounding_box(bbox, Aabb::new(v[2], v[2]));
+    Aabb::new(bbox.minimum - padding, bbox.maximum + padding)
+}
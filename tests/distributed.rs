@@ -0,0 +1,149 @@
+use raytracing::distributed::{scene_fingerprint, Coordinator, Job, Worker};
+use raytracing::renderer::{RenderObserver, RenderStatus, Tile};
+use raytracing::scene::{load_scene, Scene};
+use raytracing::Renderer;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CORNELL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/cornell.scene");
+
+fn cornell() -> Scene {
+    let mut scene = load_scene(CORNELL).unwrap();
+    scene.settings.resize(Some(40), Some(24));
+    scene.settings.samples_per_pixel = 4;
+    scene.settings.seed = 1;
+    scene
+}
+
+/// Holds every tile back until `workers` workers connected, so that they all get some
+struct AwaitWorkers {
+    workers: usize,
+    connected: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl RenderObserver for AwaitWorkers {
+    fn tile_started(&self, _tile: &Tile) {
+        let connected = self.connected.lock().unwrap();
+        let (_connected, timeout) = self
+            .changed
+            .wait_timeout_while(connected, Duration::from_secs(10), |connected| {
+                *connected < self.workers
+            })
+            .unwrap();
+        assert!(!timeout.timed_out(), "the workers did not connect");
+    }
+
+    fn worker_connected(&self, _address: SocketAddr) {
+        *self.connected.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+}
+
+#[test]
+fn loopback_render_matches_local_one() {
+    let scene = cornell();
+    let image = Renderer::new(&scene, scene.camera(), scene.settings)
+        .render()
+        .pixels;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let job = Job::new(
+        &scene.settings,
+        scene_fingerprint(Path::new(CORNELL), &scene).unwrap(),
+    );
+    let observer = AwaitWorkers {
+        workers: 2,
+        connected: Mutex::new(0),
+        changed: Condvar::new(),
+    };
+    let mut accumulation = Renderer::new(&scene, scene.camera(), scene.settings).accumulation();
+
+    thread::scope(|scope| {
+        for _ in 0..observer.workers {
+            scope.spawn(|| {
+                let worker = Worker::connect(address).unwrap();
+                let mut scene = load_scene(CORNELL).unwrap();
+                let job = worker.job().clone();
+                assert_eq!(
+                    job.scene,
+                    scene_fingerprint(Path::new(CORNELL), &scene).unwrap()
+                );
+                job.apply(&mut scene.settings);
+                worker
+                    .serve(&Renderer::new(&scene, scene.camera(), scene.settings))
+                    .unwrap();
+            });
+        }
+        Coordinator::new(listener, job)
+            .with_observer(&observer)
+            .with_tile_size(8)
+            .render(&mut accumulation)
+            .unwrap();
+    });
+
+    assert_eq!(*observer.connected.lock().unwrap(), 2);
+    assert_eq!(accumulation.average().pixels, image);
+}
+
+#[test]
+fn coordinator_gives_up_without_workers() {
+    let scene = cornell();
+    let renderer = Renderer::new(&scene, scene.camera(), scene.settings);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let job = Job::new(&scene.settings, 0);
+
+    let mut accumulation = renderer.accumulation();
+    let err = Coordinator::new(listener, job)
+        .with_wait(Duration::from_millis(200))
+        .render(&mut accumulation)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(accumulation.total_samples(), 0);
+
+    // What is left can still be rendered locally
+    assert_eq!(
+        renderer.render_into(&mut accumulation, false),
+        RenderStatus::Finished
+    );
+    assert_eq!(accumulation.average().pixels, renderer.render().pixels);
+}
+
+#[test]
+fn fingerprints_cover_referenced_files() {
+    let directory = std::env::temp_dir().join(format!("fingerprint-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let scene_path = directory.join("mesh.scene");
+    let mesh_path = directory.join("triangle.obj");
+    let library_path = directory.join("triangle.mtl");
+    std::fs::write(
+        &scene_path,
+        "camera lookfrom=0,0,5 lookat=0,0,0 vfov=40\n\
+         mesh file=triangle.obj\n",
+    )
+    .unwrap();
+
+    let fingerprint = |mesh: &str, library: &str| {
+        std::fs::write(&mesh_path, mesh).unwrap();
+        std::fs::write(&library_path, library).unwrap();
+        let scene = load_scene(&scene_path).unwrap();
+        assert_eq!(scene.files, vec![mesh_path.clone(), library_path.clone()]);
+        scene_fingerprint(&scene_path, &scene).unwrap()
+    };
+    let mesh = "mtllib triangle.mtl\nusemtl red\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    let library = "newmtl red\nKd 1 0 0\n";
+    let original = fingerprint(mesh, library);
+    let moved = fingerprint(&mesh.replace("v 1 0 0", "v 2 0 0"), library);
+    let recolored = fingerprint(mesh, &library.replace("Kd 1 0 0", "Kd 0 1 0"));
+    let unchanged = fingerprint(mesh, library);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_ne!(original, moved);
+    assert_ne!(original, recolored);
+    assert_eq!(original, unchanged);
+}
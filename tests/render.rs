@@ -0,0 +1,236 @@
+use raytracing::renderer::{Accumulation, CancelToken, RenderObserver, RenderStatus, Tile};
+use raytracing::scene::{load_scene, Scene};
+use raytracing::{checkpoint, distributed, Renderer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const CORNELL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/cornell.scene");
+
+/// The Cornell box scene at a small size, so a test renders it in a moment
+fn cornell(width: i32, height: i32, samples_per_pixel: i32) -> Scene {
+    let mut scene = load_scene(CORNELL).unwrap();
+    scene.settings.resize(Some(width), Some(height));
+    scene.settings.samples_per_pixel = samples_per_pixel;
+    scene.settings.seed = 1;
+    scene
+}
+
+#[test]
+fn renders_single_pixel_rows_and_columns() {
+    for &(width, height) in &[(1, 1), (1, 8), (8, 1)] {
+        let scene = cornell(width, height, 4);
+        let image = Renderer::new(&scene, scene.camera(), scene.settings).render();
+        assert_eq!(image.pixels.len(), (width * height) as usize);
+        for pixel in &image.pixels {
+            assert!(pixel.x.is_finite() && pixel.y.is_finite() && pixel.z.is_finite());
+        }
+        assert!(image.pixels.iter().any(|pixel| pixel.x > 0.0));
+    }
+}
+
+fn render_with_threads(scene: &Scene, threads: usize) -> Vec<raytracing::vec3::Color> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let renderer = Renderer::new(scene, scene.camera(), scene.settings);
+    pool.install(|| renderer.render()).pixels
+}
+
+#[test]
+fn renders_are_deterministic() {
+    let mut scene = cornell(48, 40, 4);
+    let single = render_with_threads(&scene, 1);
+    assert_eq!(single, render_with_threads(&scene, 1));
+    assert_eq!(single, render_with_threads(&scene, 4));
+
+    scene.settings.seed = 2;
+    assert_ne!(single, render_with_threads(&scene, 4));
+
+    scene.settings.adaptive_threshold = Some(0.05);
+    scene.settings.min_samples_per_pixel = 2;
+    assert_eq!(
+        render_with_threads(&scene, 1),
+        render_with_threads(&scene, 3)
+    );
+}
+
+/// Mean of all channels of all pixels
+fn mean(pixels: &[raytracing::vec3::Color]) -> f64 {
+    pixels
+        .iter()
+        .map(|pixel| pixel.x + pixel.y + pixel.z)
+        .sum::<f64>()
+        / (3 * pixels.len()) as f64
+}
+
+#[test]
+fn light_sampling_of_mixed_materials_is_unbiased() {
+    // A smooth half metal floor, a mirror lobe over a diffuse one, lit by a light out of view
+    let source = "\
+        render width=8 height=8 samples=1024 max_depth=2 roulette_depth=100\n\
+        camera lookfrom=0,5,0.01 lookat=0,0,0 vfov=10\n\
+        environment solid color=0,0,0\n\
+        material floor principled albedo=0.8,0.8,0.8 metallic=0.5 roughness=0\n\
+        material lamp diffuse_light color=1,1,1 intensity=4\n\
+        xz_rect x0=-3 x1=3 z0=-3 z1=3 k=0 material=floor\n\
+        xz_rect x0=1.5 x1=3.5 z0=-1 z1=1 k=3 flip=true material=lamp\n";
+    let path = std::env::temp_dir().join(format!("mixed-{}.scene", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let sampled = load_scene(&path).unwrap();
+    let mut unsampled = load_scene(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // Without lights to sample only paths that happen to reach the light find it
+    unsampled.lights = raytracing::hittable_list::HittableList::new();
+
+    let with_light_sampling = mean(
+        &Renderer::new(&sampled, sampled.camera(), sampled.settings)
+            .render()
+            .pixels,
+    );
+    let without = mean(
+        &Renderer::new(&unsampled, unsampled.camera(), unsampled.settings)
+            .render()
+            .pixels,
+    );
+    assert!(without > 0.0);
+    assert!(
+        (with_light_sampling / without - 1.0).abs() < 0.05,
+        "{} with light sampling, {} without",
+        with_light_sampling,
+        without
+    );
+}
+
+#[test]
+fn last_vertices_get_all_their_direct_light() {
+    // A panel hanging under a wide lamp, seen from below. It only gets light off the floor,
+    // whose direct light light sampling and BSDF sampling find about equally well.
+    let source = "\
+        render width=8 height=8 samples=256 max_depth=2 roulette_depth=100\n\
+        camera lookfrom=0,0.3,0 lookat=0,0.9,0 vup=0,0,1 vfov=20\n\
+        environment solid color=0,0,0\n\
+        material white lambertian albedo=0.8,0.8,0.8\n\
+        material lamp diffuse_light color=1,1,1 intensity=1\n\
+        xz_rect x0=-2 x1=2 z0=-2 z1=2 k=0 material=white\n\
+        xz_rect x0=-0.5 x1=0.5 z0=-0.5 z1=0.5 k=0.9 flip=true material=white\n\
+        xz_rect x0=-2 x1=2 z0=-2 z1=2 k=1 flip=true material=lamp\n";
+    let path = std::env::temp_dir().join(format!("last-vertex-{}.scene", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let two_rays = load_scene(&path).unwrap();
+    let mut one_bounce = load_scene(&path).unwrap();
+    let mut reference = load_scene(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let render = |scene: &Scene| {
+        mean(
+            &Renderer::new(scene, scene.camera(), scene.settings)
+                .render()
+                .pixels,
+        )
+    };
+
+    // One diffuse bounce, the direct light at the second surface comes from light sampling
+    // alone, either for the lack of another ray or of another diffuse bounce
+    one_bounce.settings.max_depth = 10;
+    one_bounce.settings.diffuse_depth = Some(1);
+    // The same paths found by BSDF sampling alone, which needs a third ray to reach the lamp
+    reference.settings.max_depth = 3;
+    reference.lights = raytracing::hittable_list::HittableList::new();
+
+    let expected = render(&reference);
+    for (name, scene) in [("max_depth=2", &two_rays), ("diffuse_depth=1", &one_bounce)] {
+        let actual = render(scene);
+        assert!(
+            (actual / expected - 1.0).abs() < 0.03,
+            "{} with {}, {} by BSDF sampling alone",
+            actual,
+            name,
+            expected
+        );
+    }
+}
+
+#[test]
+fn tile_size_does_not_change_the_image() {
+    let scene = cornell(40, 24, 2);
+    let renderer = || Renderer::new(&scene, scene.camera(), scene.settings);
+    assert_eq!(renderer().tiles().len(), 2);
+    assert_eq!(renderer().with_tile_size(7).tiles().len(), 24);
+
+    let image = renderer().render().pixels;
+    for &tile_size in &[1, 7, 64] {
+        assert_eq!(
+            image,
+            renderer().with_tile_size(tile_size).render().pixels,
+            "tile size {}",
+            tile_size
+        );
+    }
+}
+
+#[test]
+fn passes_double_up_to_the_sample_count() {
+    let mut scene = cornell(4, 4, 10);
+    let passes = |scene: &Scene| Renderer::new(scene, scene.camera(), scene.settings).passes(true);
+    assert_eq!(passes(&scene), vec![1, 2, 4, 3]);
+
+    // The pass after 2^30 samples would hold more than an i32
+    scene.settings.samples_per_pixel = i32::MAX;
+    let passes = passes(&scene);
+    assert_eq!(passes.len(), 31);
+    assert_eq!(
+        passes.iter().map(|&samples| samples as i64).sum::<i64>(),
+        i32::MAX as i64
+    );
+}
+
+/// Cancels the render once `tiles` more tiles were merged
+struct CancelAfter {
+    tiles: AtomicUsize,
+    cancel: CancelToken,
+}
+
+impl RenderObserver for CancelAfter {
+    fn tile_merged(&self, _tile: &Tile, _accumulation: &Accumulation) {
+        if self.tiles.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.cancel.cancel();
+        }
+    }
+}
+
+#[test]
+fn resumed_renders_match_uninterrupted_ones() {
+    let fingerprint = distributed::fingerprint(&std::fs::read(CORNELL).unwrap());
+    let scene = cornell(40, 24, 8);
+    let renderer = || Renderer::new(&scene, scene.camera(), scene.settings).with_tile_size(8);
+    let image = renderer().render().pixels;
+
+    // 15 tiles a pass, progressively so that the render stops in the middle of the second
+    let cancel = CancelToken::new();
+    let observer = CancelAfter {
+        tiles: AtomicUsize::new(20),
+        cancel: cancel.clone(),
+    };
+    let mut accumulation = renderer().accumulation();
+    let status = renderer()
+        .with_observer(&observer)
+        .with_cancel_token(cancel)
+        .render_into(&mut accumulation, true);
+    assert_eq!(status, RenderStatus::Cancelled);
+    let samples = accumulation.total_samples();
+    assert!(samples > 0 && samples < 40 * 24 * 8, "{} samples", samples);
+
+    let path = std::env::temp_dir().join(format!("resume-{}.checkpoint", std::process::id()));
+    checkpoint::save(&path, &scene.settings, fingerprint, &accumulation).unwrap();
+    let checkpoint = checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(checkpoint.check(&scene.settings, fingerprint ^ 1).is_err());
+    checkpoint.check(&scene.settings, fingerprint).unwrap();
+
+    let mut accumulation = checkpoint.accumulation;
+    assert_eq!(accumulation.total_samples(), samples);
+    assert_eq!(
+        renderer().render_into(&mut accumulation, true),
+        RenderStatus::Finished
+    );
+    assert_eq!(accumulation.average().pixels, image);
+}